@@ -1,11 +1,30 @@
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub general: GeneralConfig,
     pub bars: BarConfig,
-    pub colors: HashMap<String, ConfigColor>,
+    pub colors: ColorsConfig,
     pub smoothing: SmoothingConfig,
+    #[serde(default)]
+    pub postprocessing: PostprocessingConfig,
+    #[serde(default)]
+    pub beat: BeatConfig,
+    #[serde(default)]
+    pub silence: SilenceConfig,
+    #[serde(default)]
+    pub background: BackgroundConfig,
+    #[serde(default)]
+    pub render: RenderConfig,
+    #[serde(default)]
+    pub cava: CavaOptionsConfig,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    // [preset.<name>] tables, see Config::parse.
+    #[serde(default)]
+    pub preset: toml::Table,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,6 +34,7 @@ pub struct GeneralConfig {
     pub autosens: Option<bool>,
     pub sensitivity: Option<f32>,
     pub preferred_output: Option<String>,
+    pub preset: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -23,6 +43,33 @@ pub struct BarConfig {
     pub gap: f32,
 }
 
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ColorsConfig {
+    // A table of its own, so every other key of [colors] can name a gradient color.
+    pub import: Option<ColorImportConfig>,
+    // Gradient colors from bottom to top, in the order they are written.
+    #[serde(flatten)]
+    pub gradient_colors: IndexMap<String, ConfigColor>,
+}
+
+impl ColorsConfig {
+    pub fn from_image(&self) -> Option<&str> {
+        self.import.as_ref()?.from_image.as_deref()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct ColorImportConfig {
+    // Palette file written by pywal, wallust or matugen, or a base16 scheme, see theme.rs.
+    pub file: Option<String>,
+    // Names of the imported colors forming the gradient and the background.
+    pub gradient: Option<Vec<String>>,
+    pub background: Option<String>,
+    // Wallpaper to derive the gradient and background from, see image_palette.rs.
+    // "background" uses the image the background shows.
+    pub from_image: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SmoothingConfig {
     pub monstercat: Option<f32>,
@@ -30,14 +77,184 @@ pub struct SmoothingConfig {
     pub noise_reduction: Option<f32>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PostprocessingConfig {
+    #[serde(default)]
+    pub curve: ResponseCurve,
+    pub db_range: Option<f32>,
+    pub monstercat: Option<f32>,
+    pub gaussian: Option<f32>,
+    pub attack: Option<f32>,
+    pub decay: Option<f32>,
+    pub gravity: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseCurve {
+    #[default]
+    Linear,
+    Db,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct BeatConfig {
+    pub bass_bars: Option<u32>,
+    pub sensitivity: Option<f32>,
+    pub flash: Option<f32>,
+    pub flash_decay: Option<f32>,
+    pub bump: Option<f32>,
+    pub bump_decay: Option<f32>,
+    pub gradient_shift: Option<f32>,
+    pub gradient_shift_decay: Option<f32>,
+    pub glow: Option<f32>,
+    pub glow_decay: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct SilenceConfig {
+    pub enabled: Option<bool>,
+    pub threshold: Option<f32>,
+    pub timeout: Option<f32>,
+    pub fade_in: Option<f32>,
+    pub fade_out: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+pub struct BackgroundConfig {
+    // Drawn over general.background_color, bottom to top.
+    pub gradient: Option<Vec<ConfigColor>>,
+    // Drawn over the gradient.
+    pub image: Option<String>,
+    #[serde(default)]
+    pub fit: BackgroundFit,
+    // Cycles through the images in this directory instead of showing image, see slideshow.rs.
+    pub directory: Option<String>,
+    // Seconds each image is shown.
+    pub interval: Option<f32>,
+    #[serde(default)]
+    pub order: SlideshowOrder,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SlideshowOrder {
+    // By file name.
+    #[default]
+    Sequential,
+    // Every image once in random order, then again in another.
+    Shuffle,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundFit {
+    // Covers the output, cropping the image.
+    #[default]
+    Fill,
+    // Shows the whole image, leaving bars at the sides.
+    Fit,
+    // Unscaled in the middle of the output.
+    Center,
+    // Unscaled and repeated from the top left corner.
+    Tile,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct RenderConfig {
+    #[serde(default)]
+    pub backend: RenderBackend,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    pub crossfade: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    #[default]
+    None,
+    Linear,
+    Spring,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderBackend {
+    #[default]
+    Gl,
+    Software,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct AudioConfig {
+    #[serde(default)]
+    pub backend: AudioBackend,
+    // Format of cava's raw output, used by the cava and fifo backends.
+    #[serde(default)]
+    pub bit_format: BitFormat,
+    pub ascii_max_range: Option<u32>,
+    pub bar_delimiter: Option<u8>,
+    pub frame_delimiter: Option<u8>,
+    // Options below are only used by the fifo backend.
+    pub path: Option<String>,
+    pub socket: Option<String>,
+    // Options below are only used by the native and pulse backends.
+    pub source: Option<String>,
+    pub stream: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub lower_cutoff_freq: Option<f32>,
+    pub higher_cutoff_freq: Option<f32>,
+    // Options below are only used by the replay backend.
+    pub file: Option<String>,
+    #[serde(rename = "loop")]
+    pub looping: Option<bool>,
+    pub speed: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    #[default]
+    Cava,
+    Native,
+    #[serde(alias = "pipewire")]
+    Pulse,
+    Fifo,
+    Replay,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BitFormat {
+    #[serde(rename = "8bit")]
+    Bit8,
+    #[default]
+    #[serde(rename = "16bit")]
+    Bit16,
+    #[serde(rename = "ascii")]
+    Ascii,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct CavaOptionsConfig {
+    pub binary: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub input: Option<toml::Table>,
+    pub general: Option<toml::Table>,
+    pub eq: Option<toml::Table>,
+    // Only here to be rejected with a helpful message, the output is owned by wallpaper-cava.
+    pub output: Option<toml::Table>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 #[serde(untagged)]
 pub enum ConfigColor {
     Simple(String),
     Complex(HexColorConfig),
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct HexColorConfig {
     pub hex: String,
     pub alpha: Option<f32>,
@@ -47,6 +264,10 @@ pub struct HexColorConfig {
 pub struct CavaConfig {
     pub general: CavaGeneralConfig,
     pub smoothing: CavaSmoothingConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<toml::Table>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eq: Option<toml::Table>,
     pub output: HashMap<String, String>,
 }
 
@@ -56,6 +277,8 @@ pub struct CavaGeneralConfig {
     pub bars: u32,
     pub autosens: Option<bool>,
     pub sensitivity: Option<f32>,
+    #[serde(flatten)]
+    pub extra: toml::Table,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -65,6 +288,126 @@ pub struct CavaSmoothingConfig {
     pub noise_reduction: Option<f32>,
 }
 
+impl Config {
+    /// Parses a config file and applies `preset`, or else `general.preset`.
+    /// A preset's tables are merged into the sections of the same name, except
+    /// for `colors`, which is replaced as a whole.
+    pub fn parse(source: &str, preset: Option<&str>) -> Result<Self, String> {
+        let mut table: toml::Table = toml::from_str(source)
+            .map_err(|error| format!("Error parsing config: {}", error.message()))?;
+        let preset = preset.map(str::to_string).or_else(|| {
+            table
+                .get("general")
+                .and_then(|general| general.get("preset"))
+                .and_then(|preset| preset.as_str())
+                .map(str::to_string)
+        });
+        if let Some(name) = &preset {
+            let overrides = table
+                .get("preset")
+                .and_then(|presets| presets.get(name))
+                .and_then(|preset| preset.as_table())
+                .cloned()
+                .ok_or_else(|| format!("No preset named {}", name))?;
+            for (key, value) in overrides {
+                match (table.get_mut(&key), value) {
+                    (Some(toml::Value::Table(section)), toml::Value::Table(value))
+                        if key != "colors" =>
+                    {
+                        merge_table(section, value)
+                    }
+                    (_, value) => {
+                        table.insert(key, value);
+                    }
+                }
+            }
+        }
+        let mut config: Config = table.try_into().map_err(|error: toml::de::Error| {
+            format!("Error parsing config: {}", error.message())
+        })?;
+        config.general.preset = preset;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn preset_names(&self) -> Vec<&str> {
+        self.preset.keys().map(String::as_str).collect()
+    }
+
+    /// Whether switching to `other` only changes what `Appearance` covers:
+    /// the colors, the background color and the gap between bars.
+    pub fn only_appearance_differs(&self, other: &Config) -> bool {
+        fn without_appearance(config: &Config) -> Option<toml::Value> {
+            let mut value = toml::Value::try_from(config).ok()?;
+            let table = value.as_table_mut()?;
+            table.remove("colors");
+            table.remove("preset");
+            let general = table.get_mut("general")?.as_table_mut()?;
+            general.remove("background_color");
+            general.remove("preset");
+            table.get_mut("bars")?.as_table_mut()?.remove("gap");
+            Some(value)
+        }
+        match (without_appearance(self), without_appearance(other)) {
+            (Some(config), Some(other)) => config == other,
+            _ => false,
+        }
+    }
+
+    /// Catches what would otherwise panic once the renderer is created.
+    pub fn validate(&self) -> Result<(), String> {
+        for color in self
+            .colors
+            .gradient_colors
+            .values()
+            .chain([&self.general.background_color])
+            .chain(self.background.gradient.iter().flatten())
+        {
+            let hex = color.hex();
+            if !is_hex_color(hex) {
+                return Err(format!("Invalid color {}, expected #RRGGBB", hex));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConfigColor {
+    pub fn hex(&self) -> &str {
+        match self {
+            ConfigColor::Simple(hex) => hex,
+            ConfigColor::Complex(color) => &color.hex,
+        }
+    }
+}
+
+fn merge_table(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(value)) => {
+                merge_table(table, value)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Resolves a leading `~/` to the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(path), Some(home)) => PathBuf::from(home).join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+pub fn is_hex_color(color: &str) -> bool {
+    color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|digit| digit.is_ascii_hexdigit())
+}
+
 pub fn color_from_hex(hex: String, a: f32) -> [f32; 4] {
     let r = u8::from_str_radix(&hex[1..3], 16).unwrap() as f32 / 255f32;
     let g = u8::from_str_radix(&hex[3..5], 16).unwrap() as f32 / 255f32;
@@ -80,3 +423,105 @@ pub fn array_from_config_color(color: ConfigColor) -> [f32; 4] {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+        [general]
+        framerate = 60
+        background_color = '#000000'
+        [bars]
+        amount = 32
+        gap = 0.1
+        [colors]
+        color_1 = '#ff0000'
+        color_2 = '#00ff00'
+        [smoothing]
+        monstercat = 1.5
+        waves = 0
+
+        [preset.calm]
+        smoothing = { monstercat = 3.0 }
+        colors = { color_1 = '#0000ff' }
+        [preset.dense]
+        bars = { amount = 64 }
+    "##;
+
+    fn gradient(config: &Config) -> Vec<&str> {
+        config
+            .colors
+            .gradient_colors
+            .values()
+            .map(ConfigColor::hex)
+            .collect()
+    }
+
+    #[test]
+    fn merges_sections_but_replaces_colors() {
+        let config = Config::parse(CONFIG, Some("calm")).unwrap();
+        assert_eq!(config.smoothing.monstercat, Some(3.0));
+        assert_eq!(config.smoothing.waves, Some(0));
+        assert_eq!(gradient(&config), ["#0000ff"]);
+        assert_eq!(config.general.preset.as_deref(), Some("calm"));
+    }
+
+    #[test]
+    fn rejects_unknown_presets() {
+        assert_eq!(
+            Config::parse(CONFIG, Some("loud")).err(),
+            Some("No preset named loud".to_string())
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_general_preset() {
+        let config = Config::parse(CONFIG, None).unwrap();
+        assert_eq!(config.general.preset, None);
+        assert_eq!(gradient(&config), ["#ff0000", "#00ff00"]);
+
+        let source = CONFIG.replace("framerate = 60", "framerate = 60\npreset = 'calm'");
+        let config = Config::parse(&source, None).unwrap();
+        assert_eq!(config.general.preset.as_deref(), Some("calm"));
+        assert_eq!(gradient(&config), ["#0000ff"]);
+        // The command line wins.
+        let config = Config::parse(&source, Some("dense")).unwrap();
+        assert_eq!(config.bars.amount, 64);
+        assert_eq!(gradient(&config), ["#ff0000", "#00ff00"]);
+    }
+
+    #[test]
+    fn keeps_import_options_apart_from_color_names() {
+        let source = CONFIG.replace(
+            "color_2 = '#00ff00'",
+            "gradient = '#00ff00'\nbackground = '#0000ff'\n\
+             [colors.import]\nfile = 'colors.json'\ngradient = ['color1']",
+        );
+        let config = Config::parse(&source, None).unwrap();
+        assert_eq!(gradient(&config), ["#ff0000", "#00ff00", "#0000ff"]);
+        let import = config.colors.import.unwrap();
+        assert_eq!(import.file.as_deref(), Some("colors.json"));
+        assert_eq!(import.gradient, Some(vec!["color1".to_string()]));
+        assert_eq!(import.from_image, None);
+    }
+
+    #[test]
+    fn tells_appearance_changes_apart() {
+        let config = Config::parse(CONFIG, None).unwrap();
+        let changed = |from: &str, to: &str| {
+            let other = Config::parse(&CONFIG.replacen(from, to, 1), None).unwrap();
+            config.only_appearance_differs(&other)
+        };
+        assert!(config.only_appearance_differs(&Config::parse(CONFIG, None).unwrap()));
+        assert!(changed("color_2 = '#00ff00'", "color_2 = '#ffffff'"));
+        assert!(changed("gap = 0.1", "gap = 0.5"));
+        assert!(changed(
+            "background_color = '#000000'",
+            "background_color = '#101010'"
+        ));
+        assert!(!config.only_appearance_differs(&Config::parse(CONFIG, Some("calm")).unwrap()));
+        assert!(!changed("amount = 32", "amount = 48"));
+        assert!(!changed("framerate = 60", "framerate = 30"));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::app_config::{array_from_config_color, Config};
+use std::time::{Duration, Instant};
+
+/// The parts of a config that can change without rebuilding the renderer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Appearance {
+    /// Bottom to top.
+    pub gradient_colors: Vec<[f32; 4]>,
+    pub background_color: [f32; 4],
+    pub bar_gap: f32,
+}
+
+impl Appearance {
+    pub fn from_config(config: &Config) -> Self {
+        Appearance {
+            gradient_colors: config
+                .colors
+                .gradient_colors
+                .values()
+                .map(|color| array_from_config_color(color.clone()))
+                .collect(),
+            background_color: array_from_config_color(config.general.background_color.clone()),
+            bar_gap: config.bars.gap,
+        }
+    }
+
+    /// `progress` 0.0 is `self`, 1.0 is `other`. Gradients with a different
+    /// number of colors are resampled to the longer one first.
+    pub fn mix(&self, other: &Appearance, progress: f32) -> Appearance {
+        let stops = self.gradient_colors.len().max(other.gradient_colors.len());
+        let gradient_colors = (0..stops)
+            .map(|stop| {
+                let position = if stops > 1 {
+                    stop as f32 / (stops - 1) as f32
+                } else {
+                    0.0
+                };
+                mix_colors(
+                    sample_gradient(&self.gradient_colors, position),
+                    sample_gradient(&other.gradient_colors, position),
+                    progress,
+                )
+            })
+            .collect();
+        Appearance {
+            gradient_colors,
+            background_color: mix_colors(self.background_color, other.background_color, progress),
+            bar_gap: self.bar_gap + (other.bar_gap - self.bar_gap) * progress,
+        }
+    }
+}
+
+/// Color at `position` 0.0..=1.0 of a gradient, like the fragment shader.
+pub(crate) fn sample_gradient(gradient_colors: &[[f32; 4]], position: f32) -> [f32; 4] {
+    match gradient_colors {
+        [] => [0.0; 4],
+        [color] => *color,
+        _ => {
+            let findex = position.clamp(0.0, 1.0) * (gradient_colors.len() - 1) as f32;
+            let index = (findex as usize).min(gradient_colors.len() - 2);
+            mix_colors(
+                gradient_colors[index],
+                gradient_colors[index + 1],
+                findex - index as f32,
+            )
+        }
+    }
+}
+
+fn mix_colors(from: [f32; 4], to: [f32; 4], progress: f32) -> [f32; 4] {
+    [
+        from[0] + (to[0] - from[0]) * progress,
+        from[1] + (to[1] - from[1]) * progress,
+        from[2] + (to[2] - from[2]) * progress,
+        from[3] + (to[3] - from[3]) * progress,
+    ]
+}
+
+/// Moves from one appearance to another over `duration`.
+pub struct Crossfade {
+    from: Appearance,
+    to: Appearance,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Crossfade {
+    pub fn new(from: Appearance, to: Appearance, now: Instant, duration: Duration) -> Self {
+        Crossfade {
+            from,
+            to,
+            start: now,
+            duration,
+        }
+    }
+
+    pub fn sample(&self, now: Instant) -> Appearance {
+        if self.is_done(now) {
+            return self.to.clone();
+        }
+        let progress =
+            now.saturating_duration_since(self.start).as_secs_f32() / self.duration.as_secs_f32();
+        self.from.mix(&self.to, progress)
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+    const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+    fn appearance(gradient_colors: Vec<[f32; 4]>, bar_gap: f32) -> Appearance {
+        Appearance {
+            gradient_colors,
+            background_color: BLACK,
+            bar_gap,
+        }
+    }
+
+    #[test]
+    fn mixes_gradients_of_different_lengths() {
+        let from = appearance(vec![RED, BLUE], 0.0);
+        let to = appearance(vec![GREEN, GREEN, GREEN], 0.4);
+        // The two stops become three, with the middle one halfway.
+        let resampled = from.mix(&to, 0.0);
+        assert_eq!(resampled.gradient_colors, [RED, [0.5, 0.0, 0.5, 1.0], BLUE]);
+        let mixed = from.mix(&to, 0.5);
+        assert_eq!(
+            mixed.gradient_colors,
+            [
+                [0.5, 0.5, 0.0, 1.0],
+                [0.25, 0.5, 0.25, 1.0],
+                [0.0, 0.5, 0.5, 1.0]
+            ]
+        );
+        assert_eq!(mixed.bar_gap, 0.2);
+        assert_eq!(from.mix(&to, 1.0), to);
+        // Going the other way resamples `to`.
+        assert_eq!(to.mix(&from, 1.0).gradient_colors.len(), 3);
+    }
+
+    #[test]
+    fn crossfades_over_the_duration() {
+        let from = appearance(vec![RED], 0.0);
+        let to = appearance(vec![BLUE], 1.0);
+        let start = Instant::now();
+        let crossfade = Crossfade::new(from.clone(), to.clone(), start, Duration::from_secs(2));
+        assert_eq!(crossfade.sample(start), from);
+        assert_eq!(
+            crossfade.sample(start + Duration::from_secs(1)).bar_gap,
+            0.5
+        );
+        assert!(!crossfade.is_done(start + Duration::from_secs(1)));
+        assert!(crossfade.is_done(start + Duration::from_secs(2)));
+        assert_eq!(crossfade.sample(start + Duration::from_secs(3)), to);
+    }
+
+    #[test]
+    fn zero_duration_crossfades_end_at_to() {
+        let to = appearance(vec![BLUE, GREEN], 0.3);
+        let start = Instant::now();
+        let crossfade = Crossfade::new(
+            appearance(vec![RED], 0.0),
+            to.clone(),
+            start,
+            Duration::ZERO,
+        );
+        assert!(crossfade.is_done(start));
+        assert_eq!(crossfade.sample(start), to);
+    }
+}
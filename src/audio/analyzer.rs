@@ -0,0 +1,333 @@
+use crate::app_config::Config;
+use crate::postprocessing::monstercat_smoothing;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub const FFT_SIZE: usize = 4096;
+
+/// Analyzer parameters, mostly the same options cava takes.
+#[derive(Debug, Clone)]
+pub struct AnalyzerSettings {
+    pub sample_rate: u32,
+    pub framerate: u32,
+    pub bar_count: usize,
+    pub lower_cutoff_freq: f32,
+    pub higher_cutoff_freq: f32,
+    pub autosens: bool,
+    /// 1.0 means 100%.
+    pub sensitivity: f32,
+    pub monstercat: Option<f32>,
+    pub noise_reduction: f32,
+}
+
+impl AnalyzerSettings {
+    pub fn from_config(config: &Config) -> Self {
+        let sample_rate = config.audio.sample_rate.unwrap_or(44100);
+        AnalyzerSettings {
+            sample_rate,
+            framerate: config.general.framerate,
+            bar_count: config.bars.amount as usize,
+            lower_cutoff_freq: config.audio.lower_cutoff_freq.unwrap_or(50.0),
+            higher_cutoff_freq: config
+                .audio
+                .higher_cutoff_freq
+                .unwrap_or(10000.0)
+                .min(sample_rate as f32 / 2.0),
+            autosens: config.general.autosens.unwrap_or(true),
+            sensitivity: config.general.sensitivity.unwrap_or(100.0) / 100.0,
+            monstercat: config
+                .smoothing
+                .monstercat
+                .filter(|monstercat| *monstercat > 0.0),
+            noise_reduction: config.smoothing.noise_reduction.unwrap_or(0.77),
+        }
+    }
+}
+
+/// Turns mono PCM into bar values the way cava does: a Hann windowed FFT over
+/// the most recent samples, summed into log-spaced bands, scaled by
+/// (auto)sensitivity, then gravity, integral and monstercat smoothing.
+pub struct Analyzer {
+    settings: AnalyzerSettings,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    samples: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    // First and last FFT bin (inclusive) and the pink noise compensation of every band.
+    bands: Vec<(usize, usize, f32)>,
+    sensitivity: f32,
+    sensitivity_init: bool,
+    previous: Vec<f32>,
+    peaks: Vec<f32>,
+    fall: Vec<f32>,
+    memory: Vec<f32>,
+}
+
+impl Analyzer {
+    pub fn new(settings: AnalyzerSettings) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FFT_SIZE - 1) as f32).cos())
+            .collect();
+        let bands = band_bins(&settings);
+        let bar_count = settings.bar_count;
+        Analyzer {
+            sensitivity: settings.sensitivity,
+            settings,
+            fft,
+            window,
+            samples: vec![0.0; FFT_SIZE],
+            spectrum: vec![Complex::default(); FFT_SIZE],
+            bands,
+            sensitivity_init: true,
+            previous: vec![0.0; bar_count],
+            peaks: vec![0.0; bar_count],
+            fall: vec![0.0; bar_count],
+            memory: vec![0.0; bar_count],
+        }
+    }
+
+    pub fn settings(&self) -> &AnalyzerSettings {
+        &self.settings
+    }
+
+    /// Appends mono samples in -1.0..=1.0, only the last `FFT_SIZE` are kept.
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        let samples = &samples[samples.len().saturating_sub(FFT_SIZE)..];
+        self.samples.drain(..samples.len());
+        self.samples.extend_from_slice(samples);
+    }
+
+    /// Computes one frame from the samples pushed so far.
+    pub fn process(&mut self, bar_values: &mut [f32]) {
+        for (bin, (sample, window)) in self
+            .spectrum
+            .iter_mut()
+            .zip(self.samples.iter().zip(&self.window))
+        {
+            *bin = Complex::new(sample * window, 0.0);
+        }
+        self.fft.process(&mut self.spectrum);
+
+        let silence = self.samples.iter().all(|sample| *sample == 0.0);
+        let magnitude_scale = 4.0 / FFT_SIZE as f32;
+        let framerate = self.settings.framerate.max(1) as f32;
+        let noise_reduction = self.settings.noise_reduction;
+        let gravity_mod = (60.0 / framerate).powf(2.5) * 1.54 / noise_reduction.max(f32::EPSILON);
+        let integral_mod = noise_reduction.powf(framerate / 60.0);
+        let mut overshoot = false;
+        for (i, (first_bin, last_bin, eq)) in self.bands.iter().enumerate() {
+            let magnitude: f32 = self.spectrum[*first_bin..=*last_bin]
+                .iter()
+                .map(|bin| bin.norm())
+                .sum::<f32>()
+                / (last_bin - first_bin + 1) as f32;
+            let mut value = magnitude * magnitude_scale * eq * self.sensitivity;
+
+            if value < self.previous[i] && noise_reduction > 0.1 {
+                value =
+                    (self.peaks[i] * (1.0 - self.fall[i] * self.fall[i] * gravity_mod)).max(0.0);
+                self.fall[i] += 0.028;
+            } else {
+                self.peaks[i] = value;
+                self.fall[i] = 0.0;
+            }
+            self.previous[i] = value;
+
+            value += self.memory[i] * integral_mod;
+            self.memory[i] = value;
+            if value > 1.0 {
+                overshoot = true;
+            }
+            bar_values[i] = value;
+        }
+
+        if self.settings.autosens {
+            if overshoot {
+                self.sensitivity *= 0.98;
+                self.sensitivity_init = false;
+            } else if !silence {
+                self.sensitivity *= 1.001;
+                if self.sensitivity_init {
+                    self.sensitivity *= 1.1;
+                }
+            }
+        }
+
+        if let Some(monstercat) = self.settings.monstercat {
+            monstercat_smoothing(&mut bar_values[..self.bands.len()], monstercat * 1.5);
+        }
+        for value in bar_values.iter_mut().take(self.bands.len()) {
+            *value = value.clamp(0.0, 1.0);
+        }
+    }
+}
+
+// Log-spaced bands between the cutoffs, every band gets at least one bin of its own
+// as long as there are bins left.
+fn band_bins(settings: &AnalyzerSettings) -> Vec<(usize, usize, f32)> {
+    let bin_width = settings.sample_rate as f32 / FFT_SIZE as f32;
+    let bar_count = settings.bar_count.max(1);
+    let lower = settings.lower_cutoff_freq.max(bin_width);
+    let higher = settings.higher_cutoff_freq.max(lower);
+    let ratio = (higher / lower).powf(1.0 / bar_count as f32);
+    let last_usable_bin = FFT_SIZE / 2 - 1;
+    let mut bands = Vec::with_capacity(bar_count);
+    let mut next_bin = (lower / bin_width) as usize;
+    for i in 0..bar_count {
+        let upper_freq = lower * ratio.powi(i as i32 + 1);
+        let first_bin = next_bin.min(last_usable_bin);
+        let last_bin = ((upper_freq / bin_width) as usize).clamp(first_bin, last_usable_bin);
+        next_bin = last_bin + 1;
+        let center_freq = (first_bin + last_bin + 1) as f32 / 2.0 * bin_width;
+        // Music has roughly a pink spectrum, this keeps the high bands visible.
+        let eq = (center_freq / lower).sqrt();
+        bands.push((first_bin, last_bin, eq));
+    }
+    bands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(bar_count: usize) -> AnalyzerSettings {
+        AnalyzerSettings {
+            sample_rate: 44100,
+            framerate: 60,
+            bar_count,
+            lower_cutoff_freq: 50.0,
+            higher_cutoff_freq: 10000.0,
+            autosens: false,
+            sensitivity: 1.0,
+            monstercat: None,
+            noise_reduction: 0.0,
+        }
+    }
+
+    fn sine(frequency: f32, amplitude: f32) -> Vec<f32> {
+        (0..FFT_SIZE)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f32 / 44100.0).sin())
+            .collect()
+    }
+
+    fn loudest_band(bar_values: &[f32]) -> usize {
+        bar_values
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+            .unwrap()
+    }
+
+    #[test]
+    fn bands_are_ordered_and_inside_the_cutoffs() {
+        let bin_width = 44100.0 / FFT_SIZE as f32;
+        for bar_count in [1, 16, 76, 200] {
+            let bands = band_bins(&settings(bar_count));
+            assert_eq!(bands.len(), bar_count);
+            let (first_bin, _, _) = bands[0];
+            let (_, last_bin, _) = bands[bar_count - 1];
+            assert!(first_bin as f32 * bin_width > 50.0 - bin_width);
+            assert!(last_bin as f32 * bin_width <= 10000.0);
+            for (first_bin, last_bin, _) in &bands {
+                assert!(first_bin <= last_bin);
+            }
+            for pair in bands.windows(2) {
+                assert_eq!(pair[1].0, pair[0].1 + 1, "{:?}", pair);
+                assert!(pair[1].2 > pair[0].2);
+            }
+        }
+    }
+
+    #[test]
+    fn bands_stop_at_nyquist() {
+        let bands = band_bins(&AnalyzerSettings {
+            higher_cutoff_freq: 40000.0,
+            ..settings(32)
+        });
+        assert!(bands
+            .iter()
+            .all(|(first_bin, last_bin, _)| *first_bin <= *last_bin && *last_bin < FFT_SIZE / 2));
+    }
+
+    #[test]
+    fn sine_peaks_in_its_band() {
+        let bin_width = 44100.0 / FFT_SIZE as f32;
+        for band in [8, 16, 24, 31] {
+            let mut analyzer = Analyzer::new(AnalyzerSettings {
+                sensitivity: 0.2,
+                ..settings(32)
+            });
+            // Centered on a bin in the middle of the band, the window leaks into the
+            // bins next to it, and the bands next to it may be a single bin wide.
+            let (first_bin, last_bin, _) = analyzer.bands[band];
+            let frequency = (first_bin + last_bin) as f32 / 2.0 * bin_width;
+            analyzer.push_samples(&sine(frequency, 0.5));
+            let mut bar_values = vec![0.0; 32];
+            analyzer.process(&mut bar_values);
+            assert!(bar_values[band] < 1.0, "clipped at {} Hz", frequency);
+            assert_eq!(loudest_band(&bar_values), band, "at {} Hz", frequency);
+        }
+    }
+
+    #[test]
+    fn autosens_settles_below_clipping() {
+        let mut analyzer = Analyzer::new(AnalyzerSettings {
+            autosens: true,
+            noise_reduction: 0.77,
+            ..settings(32)
+        });
+        let mut bar_values = vec![0.0; 32];
+        let mut peaks = Vec::new();
+        for frame in 0..600 {
+            // Changes between frames like music, otherwise gravity holds the bars.
+            let amplitude = if frame % 2 == 0 { 0.8 } else { 0.6 };
+            analyzer.push_samples(&sine(440.0, amplitude));
+            analyzer.process(&mut bar_values);
+            peaks.push(analyzer.memory.iter().cloned().fold(0.0, f32::max));
+        }
+        let settled = &peaks[500..];
+        assert!(settled.iter().all(|peak| *peak > 0.5), "{:?}", settled);
+        let clipped = settled.iter().filter(|peak| **peak > 1.0).count();
+        assert!(clipped < settled.len() / 10, "{} frames clipped", clipped);
+        assert!(analyzer.sensitivity < 1.0);
+    }
+
+    #[test]
+    fn gravity_and_integral_let_bars_fall_gradually() {
+        let mut analyzer = Analyzer::new(AnalyzerSettings {
+            sensitivity: 0.2,
+            noise_reduction: 0.77,
+            ..settings(32)
+        });
+        let mut bar_values = vec![0.0; 32];
+        for _ in 0..120 {
+            analyzer.push_samples(&sine(1000.0, 0.5));
+            analyzer.process(&mut bar_values);
+        }
+        let band = loudest_band(&bar_values);
+        let mut previous = bar_values[band];
+        assert!(previous > 0.1);
+
+        analyzer.push_samples(&[0.0; FFT_SIZE]);
+        analyzer.process(&mut bar_values);
+        // Without noise reduction the band would drop to 0 at once.
+        assert!(bar_values[band] > previous * 0.5);
+        let mut frames_to_zero = None;
+        for frame in 0..120 {
+            // The integral may still be settling by a rounding error.
+            assert!(bar_values[band] <= previous * 1.001);
+            previous = bar_values[band];
+            if previous < 0.001 {
+                frames_to_zero = Some(frame);
+                break;
+            }
+            analyzer.process(&mut bar_values);
+        }
+        let frames_to_zero = frames_to_zero.expect("never fell to 0 within 2 seconds");
+        assert!(frames_to_zero > 5);
+    }
+}
@@ -0,0 +1,349 @@
+use super::decoder::{FrameDecoder, FrameFormat};
+use super::{publish_frames, AudioSource, FrameSlot};
+use crate::app_config::{CavaConfig, CavaGeneralConfig, CavaSmoothingConfig, Config};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// [general] keys wallpaper-cava derives from its own config.
+const OWNED_GENERAL_KEYS: [(&str, &str); 4] = [
+    ("framerate", "general.framerate"),
+    ("bars", "bars.amount"),
+    ("autosens", "general.autosens"),
+    ("sensitivity", "general.sensitivity"),
+];
+
+/// Builds the config cava is started with. Output is always raw on stdout in the
+/// format set by `audio.bit_format`, the freeform `[cava.*]` tables are merged in.
+pub fn cava_config(config: &Config) -> Result<CavaConfig, String> {
+    if config.cava.output.is_some() {
+        return Err("[cava.output] is not allowed, wallpaper-cava needs cava's raw output".into());
+    }
+    let args = config.cava.args.as_deref().unwrap_or_default();
+    if args.iter().any(|arg| arg.starts_with("-p")) {
+        return Err("-p is not allowed in cava.args, the config is passed on stdin".into());
+    }
+    let extra_general = config.cava.general.clone().unwrap_or_default();
+    for (key, replacement) in OWNED_GENERAL_KEYS {
+        if extra_general.contains_key(key) {
+            return Err(format!(
+                "cava.general.{} is set by wallpaper-cava, use {} instead",
+                key, replacement
+            ));
+        }
+    }
+    let mut cava_output_config: HashMap<String, String> = HashMap::from([
+        ("method".into(), "raw".into()),
+        ("raw_target".into(), "/dev/stdout".into()),
+    ]);
+    let format_options: Vec<(&str, String)> = match FrameFormat::from_config(&config.audio) {
+        FrameFormat::Bit8 => vec![
+            ("data_format", "binary".into()),
+            ("bit_format", "8bit".into()),
+        ],
+        FrameFormat::Bit16 => vec![
+            ("data_format", "binary".into()),
+            ("bit_format", "16bit".into()),
+        ],
+        FrameFormat::Ascii {
+            max_range,
+            bar_delimiter,
+            frame_delimiter,
+        } => vec![
+            ("data_format", "ascii".into()),
+            ("ascii_max_range", max_range.to_string()),
+            ("bar_delimiter", bar_delimiter.to_string()),
+            ("frame_delimiter", frame_delimiter.to_string()),
+        ],
+    };
+    for (key, value) in format_options {
+        cava_output_config.insert(key.into(), value);
+    }
+    Ok(CavaConfig {
+        general: CavaGeneralConfig {
+            framerate: config.general.framerate,
+            bars: config.bars.amount,
+            autosens: config.general.autosens,
+            sensitivity: config.general.sensitivity,
+            extra: extra_general,
+        },
+        smoothing: CavaSmoothingConfig {
+            monstercat: config.smoothing.monstercat,
+            waves: config.smoothing.waves,
+            noise_reduction: config.smoothing.noise_reduction,
+        },
+        input: config.cava.input.clone(),
+        eq: config.cava.eq.clone(),
+        output: cava_output_config,
+    })
+}
+
+const MIN_RESTART_DELAY: Duration = Duration::from_millis(500);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+// A cava that ran at least this long is considered healthy and the backoff resets.
+const HEALTHY_RUN_TIME: Duration = Duration::from_secs(10);
+
+/// A supervised cava child process producing normalized bar values. Its output
+/// is read on a separate thread, so only the latest complete frame is kept and
+/// taking it never blocks. When cava exits it is reaped and restarted with
+/// exponential backoff, flat bars are published while it is down.
+pub struct CavaSource {
+    process: Arc<Mutex<Option<Child>>>,
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+struct Supervisor {
+    binary: String,
+    args: Vec<String>,
+    config_string: String,
+    frame_format: FrameFormat,
+    bar_count: usize,
+    process: Arc<Mutex<Option<Child>>>,
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+impl CavaSource {
+    /// `on_frame` is called from the reader thread after every decoded frame,
+    /// e.g. to wake up an event loop. Fails if cava can't be started at all.
+    pub fn spawn(
+        config: &Config,
+        on_frame: impl Fn(&[f32]) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let cava_config = cava_config(config)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let config_string: String = toml::to_string(&cava_config)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let bar_count = config.bars.amount as usize;
+        let process = Arc::new(Mutex::new(None));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let frame_slot = FrameSlot::new(bar_count, on_frame);
+        let supervisor = Supervisor {
+            binary: config.cava.binary.clone().unwrap_or_else(|| "cava".into()),
+            args: config.cava.args.clone().unwrap_or_default(),
+            config_string,
+            frame_format: FrameFormat::from_config(&config.audio),
+            bar_count,
+            process: process.clone(),
+            stopping: stopping.clone(),
+            frame_slot: frame_slot.clone(),
+        };
+        // The first start happens on the supervisor thread too, because the
+        // parent-death signal fires when the thread that spawned cava exits.
+        let (started_sender, started_receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("cava-supervisor".into())
+            .spawn(move || supervisor.run(started_sender))?;
+        started_receiver
+            .recv()
+            .map_err(|_| io::Error::other("cava supervisor exited"))??;
+        Ok(CavaSource {
+            process,
+            stopping,
+            frame_slot,
+        })
+    }
+}
+
+impl AudioSource for CavaSource {
+    fn take_frame(&self, bar_values: &mut [f32]) -> bool {
+        self.frame_slot.take(bar_values)
+    }
+
+    /// Kills cava and stops restarting it.
+    fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(process) = self.process.lock().unwrap().as_mut() {
+            let _ = process.kill();
+        }
+    }
+}
+
+impl Drop for CavaSource {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Supervisor {
+    fn run(self, started_sender: mpsc::Sender<io::Result<()>>) {
+        let mut started_sender = Some(started_sender);
+        let mut restart_delay = MIN_RESTART_DELAY;
+        while !self.stopping.load(Ordering::SeqCst) {
+            let started_at = Instant::now();
+            match self.start() {
+                Ok(stdout) => {
+                    if let Some(sender) = started_sender.take() {
+                        let _ = sender.send(Ok(()));
+                    }
+                    self.read_frames(stdout);
+                    self.reap();
+                }
+                Err(error) => {
+                    self.reap();
+                    if let Some(sender) = started_sender.take() {
+                        let _ = sender.send(Err(error));
+                        return;
+                    }
+                    eprintln!("Failed to restart cava: {}", error);
+                }
+            }
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            self.frame_slot.publish(&vec![0.0; self.bar_count]);
+            if started_at.elapsed() >= HEALTHY_RUN_TIME {
+                restart_delay = MIN_RESTART_DELAY;
+            }
+            eprintln!("Restarting cava in {:?}", restart_delay);
+            thread::sleep(restart_delay);
+            restart_delay = (restart_delay * 2).min(MAX_RESTART_DELAY);
+        }
+    }
+
+    fn start(&self) -> io::Result<ChildStdout> {
+        let mut cmd = Command::new(&self.binary);
+        cmd.arg("-p").arg("/dev/stdin").args(&self.args);
+        cmd.stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped());
+        unsafe {
+            cmd.pre_exec(|| {
+                // Don't outlive wallpaper-cava, even if it gets SIGKILLed.
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut process = cmd.spawn()?;
+        let mut cava_stdin = process.stdin.take().unwrap();
+        let stdout = process.stdout.take().unwrap();
+        let stderr = process.stderr.take().unwrap();
+        *self.process.lock().unwrap() = Some(process);
+        thread::Builder::new()
+            .name("cava-stderr".into())
+            .spawn(move || {
+                for line in BufReader::new(stderr).lines() {
+                    match line {
+                        Ok(line) => eprintln!("cava: {}", line),
+                        Err(_) => break,
+                    }
+                }
+            })?;
+        cava_stdin.write_all(self.config_string.as_bytes())?;
+        Ok(stdout)
+    }
+
+    fn read_frames(&self, stdout: ChildStdout) {
+        let mut decoder = FrameDecoder::new(self.frame_format, self.bar_count);
+        let result = publish_frames(
+            stdout,
+            &mut decoder,
+            self.bar_count,
+            &self.frame_slot,
+            &self.stopping,
+        );
+        if let Err(error) = result {
+            if !self.stopping.load(Ordering::SeqCst) {
+                eprintln!("Stopped reading cava output: {}", error);
+            }
+        }
+    }
+
+    fn reap(&self) {
+        let process = self.process.lock().unwrap().take();
+        if let Some(mut process) = process {
+            let _ = process.kill();
+            match process.wait() {
+                Ok(status) if !self.stopping.load(Ordering::SeqCst) => {
+                    eprintln!("cava exited: {}", status)
+                }
+                Ok(_) => {}
+                Err(error) => eprintln!("Failed to wait for cava: {}", error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [general]
+        framerate = 60
+        background_color = '#000000'
+        sensitivity = 120.0
+        [bars]
+        amount = 32
+        gap = 0.1
+        [colors]
+        color_1 = '#ffffff'
+        [smoothing]
+        monstercat = 1.5
+        [audio]
+        bit_format = "ascii"
+        ascii_max_range = 500
+        [cava.general]
+        lower_cutoff_freq = 50
+        [cava.input]
+        method = "pipewire"
+    "#;
+
+    fn config_string(source: &str) -> Result<String, String> {
+        let config = Config::parse(source, None)?;
+        let cava_config = cava_config(&config)?;
+        Ok(toml::to_string(&cava_config).unwrap())
+    }
+
+    #[test]
+    fn builds_the_config_cava_is_started_with() {
+        let cava: toml::Table = toml::from_str(&config_string(CONFIG).unwrap()).unwrap();
+        let general = &cava["general"];
+        assert_eq!(general["framerate"].as_integer(), Some(60));
+        assert_eq!(general["bars"].as_integer(), Some(32));
+        assert_eq!(general["sensitivity"].as_float(), Some(120.0));
+        assert_eq!(general["lower_cutoff_freq"].as_integer(), Some(50));
+        assert_eq!(cava["smoothing"]["monstercat"].as_float(), Some(1.5));
+        assert_eq!(cava["input"]["method"].as_str(), Some("pipewire"));
+        assert!(!cava.contains_key("eq"));
+        let output = &cava["output"];
+        assert_eq!(output["method"].as_str(), Some("raw"));
+        assert_eq!(output["raw_target"].as_str(), Some("/dev/stdout"));
+        assert_eq!(output["data_format"].as_str(), Some("ascii"));
+        assert_eq!(output["ascii_max_range"].as_str(), Some("500"));
+    }
+
+    #[test]
+    fn rejects_keys_wallpaper_cava_owns() {
+        for (key, replacement) in OWNED_GENERAL_KEYS {
+            let source = CONFIG.replace("lower_cutoff_freq", key);
+            let error = config_string(&source).unwrap_err();
+            assert!(error.contains(replacement), "{}", error);
+        }
+        let source = format!("{}\n[cava.output]\nmethod = \"sdl\"", CONFIG);
+        assert!(config_string(&source)
+            .unwrap_err()
+            .contains("[cava.output]"));
+    }
+
+    #[test]
+    fn rejects_a_config_path_in_args() {
+        for args in [r#"["-p", "cava.conf"]"#, r#"["-pcava.conf"]"#] {
+            let source = CONFIG.replace(
+                "[cava.input]",
+                &format!("[cava]\nargs = {}\n[cava.input]", args),
+            );
+            assert!(config_string(&source).unwrap_err().contains("-p"));
+        }
+        let source = CONFIG.replace("[cava.input]", "[cava]\nargs = [\"-v\"]\n[cava.input]");
+        assert!(config_string(&source).is_ok());
+    }
+}
@@ -0,0 +1,272 @@
+use crate::app_config::{AudioConfig, BitFormat};
+use std::error::Error;
+use std::fmt;
+
+// An ascii frame without a delimiter is dropped once the buffer holds this many
+// frames of the longest values, the peer is sending something else.
+const MAX_ASCII_FRAMES_BUFFERED: usize = 4;
+
+/// One of cava's raw output formats, values are normalized by the largest value
+/// the format can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    Bit8,
+    Bit16,
+    /// Decimal values from 0 to `max_range`, every one followed by `bar_delimiter`,
+    /// every frame followed by `frame_delimiter`.
+    Ascii {
+        max_range: u32,
+        bar_delimiter: u8,
+        frame_delimiter: u8,
+    },
+}
+
+impl FrameFormat {
+    /// Uses cava's defaults for the ascii options that aren't set.
+    pub fn from_config(audio: &AudioConfig) -> Self {
+        match audio.bit_format {
+            BitFormat::Bit8 => FrameFormat::Bit8,
+            BitFormat::Bit16 => FrameFormat::Bit16,
+            BitFormat::Ascii => FrameFormat::Ascii {
+                max_range: audio.ascii_max_range.unwrap_or(1000).max(1),
+                bar_delimiter: audio.bar_delimiter.unwrap_or(b';'),
+                frame_delimiter: audio.frame_delimiter.unwrap_or(b'\n'),
+            },
+        }
+    }
+
+    /// Bytes per frame, `None` for ascii where it varies.
+    pub fn frame_size(&self, bar_count: usize) -> Option<usize> {
+        match self {
+            FrameFormat::Bit8 => Some(bar_count),
+            FrameFormat::Bit16 => Some(bar_count * 2),
+            FrameFormat::Ascii { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidValue(String),
+    BarCount { expected: usize, found: usize },
+    FrameTooLong(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidValue(value) => write!(f, "Invalid bar value {:?}", value),
+            DecodeError::BarCount { expected, found } => {
+                write!(f, "Expected {} bars per frame, got {}", expected, found)
+            }
+            DecodeError::FrameTooLong(size) => {
+                write!(f, "No frame delimiter in {} bytes, dropped them", size)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Splits a stream of cava's raw output into frames. Data can be fed in chunks
+/// of any size, incomplete frames are kept until the rest arrives.
+pub struct FrameDecoder {
+    format: FrameFormat,
+    bar_count: usize,
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(format: FrameFormat, bar_count: usize) -> Self {
+        FrameDecoder {
+            format,
+            bar_count,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decodes the next complete frame into `bar_values`, which must hold
+    /// `bar_count` values. Returns `Ok(false)` when more data is needed. A
+    /// malformed frame is skipped, so decoding can continue after an error.
+    pub fn next_frame(&mut self, bar_values: &mut [f32]) -> Result<bool, DecodeError> {
+        match self.format {
+            FrameFormat::Bit8 => {
+                if self.buffer.len() < self.bar_count {
+                    return Ok(false);
+                }
+                for (value, byte) in bar_values.iter_mut().zip(&self.buffer[..self.bar_count]) {
+                    *value = *byte as f32 / u8::MAX as f32;
+                }
+                self.buffer.drain(..self.bar_count);
+            }
+            FrameFormat::Bit16 => {
+                let frame_size = self.bar_count * 2;
+                if self.buffer.len() < frame_size {
+                    return Ok(false);
+                }
+                for (value, bytes) in bar_values
+                    .iter_mut()
+                    .zip(self.buffer[..frame_size].chunks_exact(2))
+                {
+                    *value = u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / u16::MAX as f32;
+                }
+                self.buffer.drain(..frame_size);
+            }
+            FrameFormat::Ascii {
+                max_range,
+                bar_delimiter,
+                frame_delimiter,
+            } => {
+                let Some(frame_end) = self.buffer.iter().position(|byte| *byte == frame_delimiter)
+                else {
+                    let digits = max_range.to_string().len();
+                    let max_size = self.bar_count * (digits + 1) * MAX_ASCII_FRAMES_BUFFERED;
+                    if self.buffer.len() > max_size {
+                        let size = self.buffer.len();
+                        self.buffer.clear();
+                        return Err(DecodeError::FrameTooLong(size));
+                    }
+                    return Ok(false);
+                };
+                let frame: Vec<u8> = self.buffer.drain(..=frame_end).collect();
+                let values = frame[..frame_end]
+                    .split(|byte| *byte == bar_delimiter)
+                    .filter(|value| !value.is_empty());
+                let mut found = 0;
+                for value in values {
+                    let parsed: u32 = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.trim().parse().ok())
+                        .ok_or_else(|| {
+                            DecodeError::InvalidValue(String::from_utf8_lossy(value).into_owned())
+                        })?;
+                    if found < self.bar_count {
+                        bar_values[found] = parsed.min(max_range) as f32 / max_range as f32;
+                    }
+                    found += 1;
+                }
+                if found != self.bar_count {
+                    return Err(DecodeError::BarCount {
+                        expected: self.bar_count,
+                        found,
+                    });
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: FrameFormat = FrameFormat::Ascii {
+        max_range: 1000,
+        bar_delimiter: b';',
+        frame_delimiter: b'\n',
+    };
+
+    #[test]
+    fn decodes_16bit_frames() {
+        let mut decoder = FrameDecoder::new(FrameFormat::Bit16, 3);
+        decoder.feed(&[0x00, 0x00, 0xff, 0xff, 0x00, 0x80]);
+        let mut bar_values = [0.0; 3];
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.0, 1.0, 32768.0 / 65535.0]);
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(false));
+    }
+
+    #[test]
+    fn decodes_8bit_frames() {
+        let mut decoder = FrameDecoder::new(FrameFormat::Bit8, 4);
+        decoder.feed(&[0, 255, 51, 102, 1, 2]);
+        let mut bar_values = [0.0; 4];
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.0, 1.0, 0.2, 0.4]);
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(false));
+    }
+
+    #[test]
+    fn keeps_partial_binary_frames() {
+        let mut decoder = FrameDecoder::new(FrameFormat::Bit16, 2);
+        let mut bar_values = [0.0; 2];
+        decoder.feed(&[0xff, 0xff, 0xff]);
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(false));
+        decoder.feed(&[0xff, 0x00]);
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [1.0, 1.0]);
+        decoder.feed(&[0x00, 0x00, 0x00]);
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn decodes_ascii_frames() {
+        let mut decoder = FrameDecoder::new(ASCII, 3);
+        decoder.feed(b"0;1000;250;\n500;7");
+        let mut bar_values = [0.0; 3];
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.0, 1.0, 0.25]);
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(false));
+        decoder.feed(b"50;1;\n");
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.5, 0.75, 0.001]);
+    }
+
+    #[test]
+    fn decodes_ascii_with_custom_delimiters_and_range() {
+        let format = FrameFormat::Ascii {
+            max_range: 255,
+            bar_delimiter: b',',
+            frame_delimiter: b'|',
+        };
+        let mut decoder = FrameDecoder::new(format, 2);
+        // Values above the range are clamped.
+        decoder.feed(b"51,300|");
+        let mut bar_values = [0.0; 2];
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.2, 1.0]);
+    }
+
+    #[test]
+    fn skips_malformed_ascii_frames() {
+        let mut decoder = FrameDecoder::new(ASCII, 2);
+        decoder.feed(b"1;x;\n1;\n1000;0;\n");
+        let mut bar_values = [0.0; 2];
+        assert_eq!(
+            decoder.next_frame(&mut bar_values),
+            Err(DecodeError::InvalidValue("x".into()))
+        );
+        assert_eq!(
+            decoder.next_frame(&mut bar_values),
+            Err(DecodeError::BarCount {
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [1.0, 0.0]);
+    }
+
+    #[test]
+    fn drops_ascii_without_frame_delimiters() {
+        let mut decoder = FrameDecoder::new(ASCII, 2);
+        let mut bar_values = [0.0; 2];
+        // Two frames of four digit values fit.
+        decoder.feed(b"1000;1000;1000;1000;");
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(false));
+        decoder.feed(&[b'1'; 64]);
+        assert_eq!(
+            decoder.next_frame(&mut bar_values),
+            Err(DecodeError::FrameTooLong(84))
+        );
+        decoder.feed(b"0;1000;\n");
+        assert_eq!(decoder.next_frame(&mut bar_values), Ok(true));
+        assert_eq!(bar_values, [0.0, 1.0]);
+    }
+}
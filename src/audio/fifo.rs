@@ -0,0 +1,141 @@
+use super::decoder::{FrameDecoder, FrameFormat};
+use super::{publish_frames, AudioSource, FrameSlot};
+use crate::app_config::Config;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Reads the raw output of a cava someone else runs, from a FIFO cava writes to
+/// (`audio.path`) or from connections to a Unix socket we listen on (`audio.socket`).
+/// `bars.amount` and the `audio` format options have to match cava's config. When the
+/// writer goes away flat bars are published until the next one shows up.
+pub struct FifoSource {
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+    socket_path: Option<String>,
+}
+
+enum Input {
+    Fifo(String),
+    Socket(UnixListener),
+}
+
+struct FrameReader {
+    input: Input,
+    frame_format: FrameFormat,
+    bar_count: usize,
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+impl FifoSource {
+    /// `on_frame` is called from the reader thread after every frame. Fails if
+    /// the socket can't be bound.
+    pub fn spawn(
+        config: &Config,
+        on_frame: impl Fn(&[f32]) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let input = match (&config.audio.path, &config.audio.socket) {
+            (Some(path), None) => Input::Fifo(path.clone()),
+            (None, Some(socket_path)) => Input::Socket(bind(socket_path)?),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The fifo backend needs either audio.path or audio.socket",
+                ))
+            }
+        };
+        let bar_count = config.bars.amount as usize;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let frame_slot = FrameSlot::new(bar_count, on_frame);
+        let reader = FrameReader {
+            input,
+            frame_format: FrameFormat::from_config(&config.audio),
+            bar_count,
+            stopping: stopping.clone(),
+            frame_slot: frame_slot.clone(),
+        };
+        thread::Builder::new()
+            .name("fifo-reader".into())
+            .spawn(move || reader.run())?;
+        Ok(FifoSource {
+            stopping,
+            frame_slot,
+            socket_path: config.audio.socket.clone(),
+        })
+    }
+}
+
+// Replaces a socket left behind by a previous run, but never any other file.
+fn bind(socket_path: &str) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(socket_path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(socket_path)?;
+        }
+    }
+    UnixListener::bind(socket_path)
+}
+
+impl AudioSource for FifoSource {
+    fn take_frame(&self, bar_values: &mut [f32]) -> bool {
+        self.frame_slot.take(bar_values)
+    }
+
+    // The reader thread may be blocked on the FIFO or socket, it stops with the process.
+    fn shutdown(&self) {
+        if !self.stopping.swap(true, Ordering::SeqCst) {
+            if let Some(socket_path) = &self.socket_path {
+                let _ = fs::remove_file(socket_path);
+            }
+        }
+    }
+}
+
+impl Drop for FifoSource {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl FrameReader {
+    fn run(self) {
+        while !self.stopping.load(Ordering::SeqCst) {
+            let result = match &self.input {
+                // Blocks until cava opens the FIFO.
+                Input::Fifo(path) => File::open(path).and_then(|file| self.read_frames(file)),
+                Input::Socket(listener) => listener
+                    .accept()
+                    .and_then(|(stream, _)| self.read_frames(stream)),
+            };
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            self.frame_slot.publish(&vec![0.0; self.bar_count]);
+            if let Err(error) = result {
+                eprintln!("Failed to read cava output: {}", error);
+                return;
+            }
+        }
+    }
+
+    // Reads frames until the writer goes away.
+    fn read_frames(&self, input: impl Read) -> io::Result<()> {
+        // A fresh decoder, so a partial frame from the last writer isn't completed.
+        let mut decoder = FrameDecoder::new(self.frame_format, self.bar_count);
+        let result = publish_frames(
+            input,
+            &mut decoder,
+            self.bar_count,
+            &self.frame_slot,
+            &self.stopping,
+        );
+        match result {
+            Err(error) if error.kind() == io::ErrorKind::ConnectionReset => Ok(()),
+            result => result,
+        }
+    }
+}
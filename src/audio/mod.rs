@@ -0,0 +1,126 @@
+//! Audio sources yielding normalized bar values, either from cava, our own child
+//! or one writing to a FIFO or socket, from the built-in analyzer fed by a PCM
+//! stream or PulseAudio, or from a recording.
+
+pub mod analyzer;
+pub mod cava;
+pub mod decoder;
+pub mod fifo;
+pub mod native;
+pub mod pulse;
+pub mod recording;
+pub mod replay;
+
+pub use cava::{cava_config, CavaSource};
+pub use fifo::FifoSource;
+pub use native::NativeSource;
+pub use pulse::PulseSource;
+pub use replay::ReplaySource;
+
+use crate::app_config::{AudioBackend, Config};
+use decoder::FrameDecoder;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Something producing frames of `bars.amount` values normalized to 0.0..=1.0
+/// on a thread of its own.
+pub trait AudioSource {
+    /// Copies the latest frame into `bar_values` if it wasn't taken yet.
+    /// Returns whether `bar_values` changed.
+    fn take_frame(&self, bar_values: &mut [f32]) -> bool;
+
+    /// Stops producing frames, called before exiting.
+    fn shutdown(&self);
+}
+
+/// Starts the source selected by `audio.backend`. `on_frame` is called from the
+/// source's thread with every new frame, e.g. to record it and wake up an event loop.
+pub fn spawn_audio_source(
+    config: &Config,
+    on_frame: impl Fn(&[f32]) + Send + Sync + 'static,
+) -> io::Result<Box<dyn AudioSource>> {
+    Ok(match config.audio.backend {
+        AudioBackend::Cava => Box::new(CavaSource::spawn(config, on_frame)?),
+        AudioBackend::Native => Box::new(NativeSource::spawn(config, on_frame)?),
+        AudioBackend::Pulse => Box::new(PulseSource::spawn(config, on_frame)?),
+        AudioBackend::Fifo => Box::new(FifoSource::spawn(config, on_frame)?),
+        AudioBackend::Replay => Box::new(ReplaySource::spawn(config, on_frame)?),
+    })
+}
+
+type FrameCallback = dyn Fn(&[f32]) + Send + Sync;
+
+/// Latest complete frame, shared between the thread producing frames and the
+/// one taking them. Older frames that were never taken are dropped.
+#[derive(Clone)]
+pub struct FrameSlot {
+    latest_frame: Arc<Mutex<LatestFrame>>,
+    on_frame: Arc<FrameCallback>,
+}
+
+struct LatestFrame {
+    bar_values: Vec<f32>,
+    fresh: bool,
+}
+
+impl FrameSlot {
+    pub fn new(bar_count: usize, on_frame: impl Fn(&[f32]) + Send + Sync + 'static) -> Self {
+        FrameSlot {
+            latest_frame: Arc::new(Mutex::new(LatestFrame {
+                bar_values: vec![0.0; bar_count],
+                fresh: false,
+            })),
+            on_frame: Arc::new(on_frame),
+        }
+    }
+
+    pub fn publish(&self, bar_values: &[f32]) {
+        {
+            let mut latest_frame = self.latest_frame.lock().unwrap();
+            latest_frame.bar_values.copy_from_slice(bar_values);
+            latest_frame.fresh = true;
+        }
+        (self.on_frame)(bar_values);
+    }
+
+    pub fn take(&self, bar_values: &mut [f32]) -> bool {
+        let mut latest_frame = self.latest_frame.lock().unwrap();
+        if !latest_frame.fresh {
+            return false;
+        }
+        bar_values.copy_from_slice(&latest_frame.bar_values);
+        latest_frame.fresh = false;
+        true
+    }
+}
+
+// Decodes and publishes frames from `input` until it ends or `stopping` is set.
+// Malformed frames are reported and skipped.
+fn publish_frames(
+    mut input: impl Read,
+    decoder: &mut FrameDecoder,
+    bar_count: usize,
+    frame_slot: &FrameSlot,
+    stopping: &AtomicBool,
+) -> io::Result<()> {
+    let mut read_buffer = [0u8; 4096];
+    let mut bar_values = vec![0.0; bar_count];
+    while !stopping.load(Ordering::SeqCst) {
+        let read = match input.read(&mut read_buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        decoder.feed(&read_buffer[..read]);
+        loop {
+            match decoder.next_frame(&mut bar_values) {
+                Ok(true) => frame_slot.publish(&bar_values),
+                Ok(false) => break,
+                Err(error) => eprintln!("Skipping a malformed frame: {}", error),
+            }
+        }
+    }
+    Ok(())
+}
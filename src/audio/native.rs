@@ -0,0 +1,183 @@
+use super::analyzer::{Analyzer, AnalyzerSettings};
+use super::{AudioSource, FrameSlot};
+use crate::app_config::Config;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Runs the built-in analyzer on signed 16bit little-endian interleaved PCM read
+/// from a FIFO (`audio.source`) or stdin (`audio.source = "-"`, the default).
+/// A FIFO is reopened when its writer goes away, flat bars are published meanwhile.
+pub struct NativeSource {
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+struct PcmReader {
+    source: String,
+    pcm_analyzer: PcmAnalyzer,
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+/// Cuts signed 16bit little-endian interleaved PCM into one hop per frame,
+/// downmixes it and publishes the analyzed bars.
+pub(super) struct PcmAnalyzer {
+    channels: usize,
+    hop_size: usize,
+    analyzer: Analyzer,
+    pending: Vec<u8>,
+    samples: Vec<f32>,
+    bar_values: Vec<f32>,
+}
+
+impl NativeSource {
+    /// `on_frame` is called from the analyzer thread after every frame.
+    pub fn spawn(
+        config: &Config,
+        on_frame: impl Fn(&[f32]) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let pcm_analyzer = PcmAnalyzer::new(config)?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let frame_slot = FrameSlot::new(config.bars.amount as usize, on_frame);
+        let reader = PcmReader {
+            source: config.audio.source.clone().unwrap_or_else(|| "-".into()),
+            pcm_analyzer,
+            stopping: stopping.clone(),
+            frame_slot: frame_slot.clone(),
+        };
+        thread::Builder::new()
+            .name("native-analyzer".into())
+            .spawn(move || reader.run())?;
+        Ok(NativeSource {
+            stopping,
+            frame_slot,
+        })
+    }
+}
+
+impl AudioSource for NativeSource {
+    fn take_frame(&self, bar_values: &mut [f32]) -> bool {
+        self.frame_slot.take(bar_values)
+    }
+
+    // The reader thread may be blocked on the FIFO, it stops with the process.
+    fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for NativeSource {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl PcmReader {
+    fn run(mut self) {
+        let bar_count = self.pcm_analyzer.bar_count();
+        while !self.stopping.load(Ordering::SeqCst) {
+            let result = if self.source == "-" {
+                self.analyze(io::stdin().lock())
+            } else {
+                // Blocks until a writer opens the FIFO.
+                File::open(&self.source).and_then(|file| self.analyze(file))
+            };
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            self.frame_slot.publish(&vec![0.0; bar_count]);
+            match result {
+                Ok(()) if self.source == "-" => {
+                    eprintln!("Audio input on stdin ended");
+                    return;
+                }
+                Ok(()) => {}
+                Err(error) => {
+                    eprintln!("Failed to read audio from {}: {}", self.source, error);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Analyzes until EOF.
+    fn analyze(&mut self, mut input: impl Read) -> io::Result<()> {
+        let mut pcm_buffer = vec![0u8; self.pcm_analyzer.hop_size()];
+        while !self.stopping.load(Ordering::SeqCst) {
+            if let Err(error) = input.read_exact(&mut pcm_buffer) {
+                return match error.kind() {
+                    io::ErrorKind::UnexpectedEof => Ok(()),
+                    _ => Err(error),
+                };
+            }
+            self.pcm_analyzer.feed(&pcm_buffer, &self.frame_slot);
+        }
+        Ok(())
+    }
+}
+
+impl PcmAnalyzer {
+    /// Takes `audio.channels` and the analyzer settings from `config`.
+    pub(super) fn new(config: &Config) -> io::Result<Self> {
+        let channels = config.audio.channels.unwrap_or(2) as usize;
+        if channels == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "audio.channels must be at least 1",
+            ));
+        }
+        if config.smoothing.waves.unwrap_or(0) != 0 {
+            eprintln!("smoothing.waves is not supported by the native analyzer, ignoring it");
+        }
+        let settings = AnalyzerSettings::from_config(config);
+        // One frame per framerate-th of a second of audio.
+        let hop = (settings.sample_rate / settings.framerate.max(1)).max(1) as usize;
+        Ok(PcmAnalyzer {
+            channels,
+            hop_size: hop * channels * 2,
+            bar_values: vec![0.0; settings.bar_count],
+            samples: Vec::with_capacity(hop),
+            pending: Vec::new(),
+            analyzer: Analyzer::new(settings),
+        })
+    }
+
+    pub(super) fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub(super) fn bar_count(&self) -> usize {
+        self.bar_values.len()
+    }
+
+    /// Bytes of PCM per frame.
+    pub(super) fn hop_size(&self) -> usize {
+        self.hop_size
+    }
+
+    /// Buffers `pcm` and publishes a frame for every complete hop.
+    pub(super) fn feed(&mut self, pcm: &[u8], frame_slot: &FrameSlot) {
+        self.pending.extend_from_slice(pcm);
+        let mut offset = 0;
+        while self.pending.len() - offset >= self.hop_size {
+            self.samples.clear();
+            for frame in
+                self.pending[offset..offset + self.hop_size].chunks_exact(self.channels * 2)
+            {
+                let sum: f32 = frame
+                    .chunks_exact(2)
+                    .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32)
+                    .sum();
+                self.samples.push(sum / self.channels as f32 / 32768.0);
+            }
+            self.analyzer.push_samples(&self.samples);
+            self.analyzer.process(&mut self.bar_values);
+            frame_slot.publish(&self.bar_values);
+            offset += self.hop_size;
+        }
+        self.pending.drain(..offset);
+    }
+}
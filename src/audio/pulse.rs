@@ -0,0 +1,705 @@
+use super::native::PcmAnalyzer;
+use super::{AudioSource, FrameSlot};
+use crate::app_config::Config;
+use std::cell::Cell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+
+/// Captures a PulseAudio source, the default sink's monitor unless `audio.source`
+/// names another one, and runs the built-in analyzer on it. With `audio.stream`
+/// only the playback stream of that application is captured instead. PipeWire is
+/// supported through pipewire-pulse. libpulse is loaded at runtime, so it's only
+/// needed when this backend is used.
+pub struct PulseSource {
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+impl PulseSource {
+    /// `on_frame` is called from the capture thread after every frame. Fails if
+    /// libpulse can't be loaded, connection problems are retried in the background.
+    pub fn spawn(
+        config: &Config,
+        on_frame: impl Fn(&[f32]) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let lib = PulseLib::load().map_err(io::Error::other)?;
+        let pcm_analyzer = PcmAnalyzer::new(config)?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let frame_slot = FrameSlot::new(config.bars.amount as usize, on_frame);
+        let source = config
+            .audio
+            .source
+            .clone()
+            .filter(|source| source != "auto");
+        let capture = Capture {
+            lib,
+            follow_default: source.is_none(),
+            source: cstring(&source.unwrap_or_else(|| DEFAULT_MONITOR.into()))?,
+            stream: config.audio.stream.clone(),
+            sample_spec: PaSampleSpec {
+                format: PA_SAMPLE_S16LE,
+                rate: config.audio.sample_rate.unwrap_or(44100),
+                channels: pcm_analyzer.channels() as u8,
+            },
+            pcm_analyzer,
+            stopping: stopping.clone(),
+            frame_slot: frame_slot.clone(),
+        };
+        thread::Builder::new()
+            .name("pulse-capture".into())
+            .spawn(move || capture.run())?;
+        Ok(PulseSource {
+            stopping,
+            frame_slot,
+        })
+    }
+}
+
+impl AudioSource for PulseSource {
+    fn take_frame(&self, bar_values: &mut [f32]) -> bool {
+        self.frame_slot.take(bar_values)
+    }
+
+    // The capture thread notices it with the next event, or stops with the process.
+    fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for PulseSource {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn cstring(value: &str) -> io::Result<CString> {
+    CString::new(value).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+}
+
+struct Capture {
+    lib: PulseLib,
+    follow_default: bool,
+    source: CString,
+    stream: Option<String>,
+    sample_spec: PaSampleSpec,
+    pcm_analyzer: PcmAnalyzer,
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+// Set from the subscription callback, cleared by the capture loop. Server
+// changes include the default sink changing, but also e.g. the default source.
+#[derive(Default)]
+struct Events {
+    server_changed: Cell<bool>,
+    sink_input_added: Cell<bool>,
+}
+
+impl Capture {
+    fn run(mut self) {
+        loop {
+            match self.capture() {
+                Ok(()) => return,
+                Err(error) => eprintln!("PulseAudio capture failed: {}", error),
+            }
+            self.publish_silence();
+            thread::sleep(RECONNECT_DELAY);
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+
+    fn publish_silence(&self) {
+        self.frame_slot
+            .publish(&vec![0.0; self.pcm_analyzer.bar_count()]);
+    }
+
+    // Returns Ok once stopping, errors mean the server connection is gone.
+    fn capture(&mut self) -> Result<(), String> {
+        let events = Box::<Events>::default();
+        let connection = Connection::new(&self.lib, &events)?;
+        loop {
+            if self.stopping.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            events.server_changed.set(false);
+            events.sink_input_added.set(false);
+            let target = match &self.stream {
+                Some(name) => connection.find_stream(name)?,
+                None => Some((self.source.clone(), None)),
+            };
+            // Only known for @DEFAULT_MONITOR@, which is resolved on connect.
+            let default_sink = if self.follow_default && self.stream.is_none() {
+                Some(connection.default_sink()?)
+            } else {
+                None
+            };
+            let Some((device, sink_input)) = target else {
+                // Wait for the application to start playing.
+                self.publish_silence();
+                while !events.sink_input_added.get() {
+                    connection.iterate()?;
+                    if self.stopping.load(Ordering::SeqCst) {
+                        return Ok(());
+                    }
+                }
+                continue;
+            };
+            let stream = connection.record(
+                &device,
+                sink_input,
+                &self.sample_spec,
+                self.pcm_analyzer.hop_size() as u32,
+                !self.follow_default || sink_input.is_some(),
+            )?;
+            loop {
+                connection.iterate()?;
+                if self.stopping.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                match unsafe { (self.lib.pa_stream_get_state)(stream.stream) } {
+                    PA_STREAM_READY => stream.read(&mut self.pcm_analyzer, &self.frame_slot),
+                    PA_STREAM_FAILED | PA_STREAM_TERMINATED => break,
+                    _ => {}
+                }
+                if let Some(default_sink) = &default_sink {
+                    if events.server_changed.replace(false)
+                        && connection.default_sink()? != *default_sink
+                    {
+                        break;
+                    }
+                }
+            }
+            drop(stream);
+            self.publish_silence();
+        }
+    }
+}
+
+/// A context on a mainloop that is only run from the capture thread.
+struct Connection<'a> {
+    lib: &'a PulseLib,
+    mainloop: *mut PaMainloop,
+    context: *mut PaContext,
+}
+
+struct RecordStream<'a> {
+    lib: &'a PulseLib,
+    stream: *mut PaStream,
+}
+
+// What `find_stream` looks for and what it found.
+struct StreamQuery<'a> {
+    lib: &'a PulseLib,
+    name: &'a str,
+    sink_input: Option<(u32, u32)>,
+    monitor_source: Option<CString>,
+}
+
+impl<'a> Connection<'a> {
+    fn new(lib: &'a PulseLib, events: &Events) -> Result<Self, String> {
+        unsafe {
+            let mainloop = (lib.pa_mainloop_new)();
+            if mainloop.is_null() {
+                return Err("pa_mainloop_new failed".into());
+            }
+            let context = (lib.pa_context_new)(
+                (lib.pa_mainloop_get_api)(mainloop),
+                c"wallpaper-cava".as_ptr(),
+            );
+            if context.is_null() {
+                (lib.pa_mainloop_free)(mainloop);
+                return Err("pa_context_new failed".into());
+            }
+            let connection = Connection {
+                lib,
+                mainloop,
+                context,
+            };
+            if (lib.pa_context_connect)(context, ptr::null(), 0, ptr::null()) < 0 {
+                return Err(connection.error());
+            }
+            while (lib.pa_context_get_state)(context) != PA_CONTEXT_READY {
+                connection.iterate()?;
+            }
+            (lib.pa_context_set_subscribe_callback)(
+                context,
+                Some(subscribe_callback),
+                events as *const Events as *mut c_void,
+            );
+            connection.wait((lib.pa_context_subscribe)(
+                context,
+                PA_SUBSCRIPTION_MASK_SINK_INPUT | PA_SUBSCRIPTION_MASK_SERVER,
+                None,
+                ptr::null_mut(),
+            ))?;
+            Ok(connection)
+        }
+    }
+
+    fn error(&self) -> String {
+        unsafe {
+            let message = (self.lib.pa_strerror)((self.lib.pa_context_errno)(self.context));
+            CStr::from_ptr(message).to_string_lossy().into_owned()
+        }
+    }
+
+    // Blocks until at least one event was dispatched.
+    fn iterate(&self) -> Result<(), String> {
+        unsafe {
+            if (self.lib.pa_mainloop_iterate)(self.mainloop, 1, ptr::null_mut()) < 0 {
+                return Err("mainloop quit".into());
+            }
+            match (self.lib.pa_context_get_state)(self.context) {
+                PA_CONTEXT_FAILED | PA_CONTEXT_TERMINATED => Err(self.error()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn wait(&self, operation: *mut PaOperation) -> Result<(), String> {
+        if operation.is_null() {
+            return Err(self.error());
+        }
+        let result = loop {
+            if unsafe { (self.lib.pa_operation_get_state)(operation) } != PA_OPERATION_RUNNING {
+                break Ok(());
+            }
+            if let Err(error) = self.iterate() {
+                break Err(error);
+            }
+        };
+        unsafe { (self.lib.pa_operation_unref)(operation) };
+        result
+    }
+
+    /// Name of the sink @DEFAULT_MONITOR@ currently belongs to.
+    fn default_sink(&self) -> Result<Option<CString>, String> {
+        let mut default_sink: Option<CString> = None;
+        unsafe {
+            self.wait((self.lib.pa_context_get_server_info)(
+                self.context,
+                server_info_callback,
+                &mut default_sink as *mut Option<CString> as *mut c_void,
+            ))?;
+        }
+        Ok(default_sink)
+    }
+
+    /// Looks up a playback stream by application or stream name, returns the
+    /// monitor of the sink it plays on and its sink input index.
+    fn find_stream(&self, name: &str) -> Result<Option<(CString, Option<u32>)>, String> {
+        let mut query = StreamQuery {
+            lib: self.lib,
+            name,
+            sink_input: None,
+            monitor_source: None,
+        };
+        let query_ptr = &mut query as *mut StreamQuery as *mut c_void;
+        unsafe {
+            self.wait((self.lib.pa_context_get_sink_input_info_list)(
+                self.context,
+                sink_input_info_callback,
+                query_ptr,
+            ))?;
+        }
+        let Some((sink_input, sink)) = query.sink_input else {
+            return Ok(None);
+        };
+        unsafe {
+            self.wait((self.lib.pa_context_get_sink_info_by_index)(
+                self.context,
+                sink,
+                sink_info_callback,
+                query_ptr,
+            ))?;
+        }
+        Ok(query
+            .monitor_source
+            .map(|monitor_source| (monitor_source, Some(sink_input))))
+    }
+
+    fn record(
+        &self,
+        device: &CStr,
+        sink_input: Option<u32>,
+        sample_spec: &PaSampleSpec,
+        fragment_size: u32,
+        dont_move: bool,
+    ) -> Result<RecordStream<'a>, String> {
+        unsafe {
+            let stream = (self.lib.pa_stream_new)(
+                self.context,
+                c"visualizer".as_ptr(),
+                sample_spec,
+                ptr::null(),
+            );
+            if stream.is_null() {
+                return Err(self.error());
+            }
+            let stream = RecordStream {
+                lib: self.lib,
+                stream,
+            };
+            if let Some(sink_input) = sink_input {
+                (self.lib.pa_stream_set_monitor_stream)(stream.stream, sink_input);
+            }
+            let buffer_attr = PaBufferAttr {
+                maxlength: u32::MAX,
+                tlength: u32::MAX,
+                prebuf: u32::MAX,
+                minreq: u32::MAX,
+                fragsize: fragment_size,
+            };
+            let mut flags = PA_STREAM_ADJUST_LATENCY;
+            if dont_move {
+                flags |= PA_STREAM_DONT_MOVE;
+            }
+            if (self.lib.pa_stream_connect_record)(
+                stream.stream,
+                device.as_ptr(),
+                &buffer_attr,
+                flags,
+            ) < 0
+            {
+                return Err(self.error());
+            }
+            Ok(stream)
+        }
+    }
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.pa_context_disconnect)(self.context);
+            (self.lib.pa_context_unref)(self.context);
+            (self.lib.pa_mainloop_free)(self.mainloop);
+        }
+    }
+}
+
+impl RecordStream<'_> {
+    // Feeds everything that arrived so far to the analyzer.
+    fn read(&self, pcm_analyzer: &mut PcmAnalyzer, frame_slot: &FrameSlot) {
+        loop {
+            let mut data: *const c_void = ptr::null();
+            let mut size = 0;
+            unsafe {
+                if (self.lib.pa_stream_peek)(self.stream, &mut data, &mut size) < 0 || size == 0 {
+                    return;
+                }
+                // A null pointer with a size is a hole in the stream.
+                if !data.is_null() {
+                    pcm_analyzer.feed(
+                        std::slice::from_raw_parts(data as *const u8, size),
+                        frame_slot,
+                    );
+                }
+                (self.lib.pa_stream_drop)(self.stream);
+            }
+        }
+    }
+}
+
+impl Drop for RecordStream<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.pa_stream_disconnect)(self.stream);
+            (self.lib.pa_stream_unref)(self.stream);
+        }
+    }
+}
+
+unsafe extern "C" fn subscribe_callback(
+    _context: *mut PaContext,
+    event: u32,
+    _index: u32,
+    userdata: *mut c_void,
+) {
+    let events = &*(userdata as *const Events);
+    match (
+        event & PA_SUBSCRIPTION_EVENT_FACILITY_MASK,
+        event & PA_SUBSCRIPTION_EVENT_TYPE_MASK,
+    ) {
+        (PA_SUBSCRIPTION_EVENT_SERVER, _) => events.server_changed.set(true),
+        (PA_SUBSCRIPTION_EVENT_SINK_INPUT, PA_SUBSCRIPTION_EVENT_NEW) => {
+            events.sink_input_added.set(true)
+        }
+        _ => {}
+    }
+}
+
+unsafe extern "C" fn server_info_callback(
+    _context: *mut PaContext,
+    info: *const PaServerInfo,
+    userdata: *mut c_void,
+) {
+    let default_sink = &mut *(userdata as *mut Option<CString>);
+    if info.is_null() || (*info).default_sink_name.is_null() {
+        return;
+    }
+    *default_sink = Some(CStr::from_ptr((*info).default_sink_name).to_owned());
+}
+
+unsafe extern "C" fn sink_input_info_callback(
+    _context: *mut PaContext,
+    info: *const PaSinkInputInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    let query = &mut *(userdata as *mut StreamQuery);
+    if eol != 0 || info.is_null() || query.sink_input.is_some() {
+        return;
+    }
+    let info = &*info;
+    let application_name =
+        (query.lib.pa_proplist_gets)(info.proplist, c"application.name".as_ptr());
+    let matches = |name: *const c_char| {
+        !name.is_null()
+            && CStr::from_ptr(name)
+                .to_string_lossy()
+                .eq_ignore_ascii_case(query.name)
+    };
+    if matches(application_name) || matches(info.name) {
+        query.sink_input = Some((info.index, info.sink));
+    }
+}
+
+unsafe extern "C" fn sink_info_callback(
+    _context: *mut PaContext,
+    info: *const PaSinkInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    let query = &mut *(userdata as *mut StreamQuery);
+    if eol != 0 || info.is_null() || (*info).monitor_source_name.is_null() {
+        return;
+    }
+    query.monitor_source = Some(CStr::from_ptr((*info).monitor_source_name).to_owned());
+}
+
+// The parts of the libpulse ABI used above, see pulse/def.h, sample.h and introspect.h.
+
+#[repr(C)]
+struct PaMainloop {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PaMainloopApi {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PaContext {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PaStream {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PaOperation {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PaProplist {
+    _private: [u8; 0],
+}
+
+const PA_CHANNELS_MAX: usize = 32;
+
+#[repr(C)]
+struct PaSampleSpec {
+    format: c_int,
+    rate: u32,
+    channels: u8,
+}
+
+#[repr(C)]
+struct PaChannelMap {
+    channels: u8,
+    map: [c_int; PA_CHANNELS_MAX],
+}
+
+#[repr(C)]
+struct PaCvolume {
+    channels: u8,
+    values: [u32; PA_CHANNELS_MAX],
+}
+
+#[repr(C)]
+struct PaBufferAttr {
+    maxlength: u32,
+    tlength: u32,
+    prebuf: u32,
+    minreq: u32,
+    fragsize: u32,
+}
+
+// Leading fields of pa_sink_input_info, libpulse only ever appends fields.
+#[repr(C)]
+struct PaSinkInputInfo {
+    index: u32,
+    name: *const c_char,
+    owner_module: u32,
+    client: u32,
+    sink: u32,
+    sample_spec: PaSampleSpec,
+    channel_map: PaChannelMap,
+    volume: PaCvolume,
+    buffer_usec: u64,
+    sink_usec: u64,
+    resample_method: *const c_char,
+    driver: *const c_char,
+    mute: c_int,
+    proplist: *mut PaProplist,
+}
+
+#[repr(C)]
+struct PaServerInfo {
+    user_name: *const c_char,
+    host_name: *const c_char,
+    server_version: *const c_char,
+    server_name: *const c_char,
+    sample_spec: PaSampleSpec,
+    default_sink_name: *const c_char,
+    default_source_name: *const c_char,
+    cookie: u32,
+    channel_map: PaChannelMap,
+}
+
+// Leading fields of pa_sink_info.
+#[repr(C)]
+struct PaSinkInfo {
+    name: *const c_char,
+    index: u32,
+    description: *const c_char,
+    sample_spec: PaSampleSpec,
+    channel_map: PaChannelMap,
+    owner_module: u32,
+    volume: PaCvolume,
+    mute: c_int,
+    monitor_source: u32,
+    monitor_source_name: *const c_char,
+}
+
+const PA_SAMPLE_S16LE: c_int = 3;
+
+const PA_CONTEXT_READY: c_int = 4;
+const PA_CONTEXT_FAILED: c_int = 5;
+const PA_CONTEXT_TERMINATED: c_int = 6;
+
+const PA_STREAM_READY: c_int = 2;
+const PA_STREAM_FAILED: c_int = 3;
+const PA_STREAM_TERMINATED: c_int = 4;
+
+const PA_STREAM_DONT_MOVE: c_int = 0x0200;
+const PA_STREAM_ADJUST_LATENCY: c_int = 0x2000;
+
+const PA_OPERATION_RUNNING: c_int = 0;
+
+const PA_SUBSCRIPTION_MASK_SINK_INPUT: u32 = 0x0004;
+const PA_SUBSCRIPTION_MASK_SERVER: u32 = 0x0080;
+const PA_SUBSCRIPTION_EVENT_SINK_INPUT: u32 = 0x0002;
+const PA_SUBSCRIPTION_EVENT_SERVER: u32 = 0x0007;
+const PA_SUBSCRIPTION_EVENT_FACILITY_MASK: u32 = 0x000F;
+const PA_SUBSCRIPTION_EVENT_NEW: u32 = 0x0000;
+const PA_SUBSCRIPTION_EVENT_TYPE_MASK: u32 = 0x0030;
+
+type SubscribeCallback = unsafe extern "C" fn(*mut PaContext, u32, u32, *mut c_void);
+type SuccessCallback = unsafe extern "C" fn(*mut PaContext, c_int, *mut c_void);
+type ServerInfoCallback = unsafe extern "C" fn(*mut PaContext, *const PaServerInfo, *mut c_void);
+type SinkInputInfoCallback =
+    unsafe extern "C" fn(*mut PaContext, *const PaSinkInputInfo, c_int, *mut c_void);
+type SinkInfoCallback = unsafe extern "C" fn(*mut PaContext, *const PaSinkInfo, c_int, *mut c_void);
+
+macro_rules! pulse_lib {
+    ($($name:ident: $type:ty,)*) => {
+        struct PulseLib {
+            $($name: $type,)*
+        }
+
+        impl PulseLib {
+            fn load() -> Result<Self, String> {
+                unsafe {
+                    let handle = libc::dlopen(c"libpulse.so.0".as_ptr(), libc::RTLD_NOW);
+                    if handle.is_null() {
+                        return Err("Failed to load libpulse.so.0".into());
+                    }
+                    Ok(PulseLib {
+                        $($name: {
+                            let symbol = libc::dlsym(
+                                handle,
+                                concat!(stringify!($name), "\0").as_ptr() as *const c_char,
+                            );
+                            if symbol.is_null() {
+                                return Err(format!("libpulse has no {}", stringify!($name)));
+                            }
+                            std::mem::transmute::<*mut c_void, $type>(symbol)
+                        },)*
+                    })
+                }
+            }
+        }
+    };
+}
+
+pulse_lib! {
+    pa_mainloop_new: unsafe extern "C" fn() -> *mut PaMainloop,
+    pa_mainloop_get_api: unsafe extern "C" fn(*mut PaMainloop) -> *mut PaMainloopApi,
+    pa_mainloop_iterate: unsafe extern "C" fn(*mut PaMainloop, c_int, *mut c_int) -> c_int,
+    pa_mainloop_free: unsafe extern "C" fn(*mut PaMainloop),
+    pa_context_new: unsafe extern "C" fn(*mut PaMainloopApi, *const c_char) -> *mut PaContext,
+    pa_context_connect:
+        unsafe extern "C" fn(*mut PaContext, *const c_char, c_int, *const c_void) -> c_int,
+    pa_context_get_state: unsafe extern "C" fn(*mut PaContext) -> c_int,
+    pa_context_errno: unsafe extern "C" fn(*mut PaContext) -> c_int,
+    pa_context_disconnect: unsafe extern "C" fn(*mut PaContext),
+    pa_context_unref: unsafe extern "C" fn(*mut PaContext),
+    pa_context_set_subscribe_callback:
+        unsafe extern "C" fn(*mut PaContext, Option<SubscribeCallback>, *mut c_void),
+    pa_context_subscribe: unsafe extern "C" fn(
+        *mut PaContext,
+        u32,
+        Option<SuccessCallback>,
+        *mut c_void,
+    ) -> *mut PaOperation,
+    pa_context_get_server_info:
+        unsafe extern "C" fn(*mut PaContext, ServerInfoCallback, *mut c_void) -> *mut PaOperation,
+    pa_context_get_sink_input_info_list:
+        unsafe extern "C" fn(*mut PaContext, SinkInputInfoCallback, *mut c_void) -> *mut PaOperation,
+    pa_context_get_sink_info_by_index:
+        unsafe extern "C" fn(*mut PaContext, u32, SinkInfoCallback, *mut c_void) -> *mut PaOperation,
+    pa_operation_get_state: unsafe extern "C" fn(*mut PaOperation) -> c_int,
+    pa_operation_unref: unsafe extern "C" fn(*mut PaOperation),
+    pa_stream_new: unsafe extern "C" fn(
+        *mut PaContext,
+        *const c_char,
+        *const PaSampleSpec,
+        *const c_void,
+    ) -> *mut PaStream,
+    pa_stream_set_monitor_stream: unsafe extern "C" fn(*mut PaStream, u32) -> c_int,
+    pa_stream_connect_record:
+        unsafe extern "C" fn(*mut PaStream, *const c_char, *const PaBufferAttr, c_int) -> c_int,
+    pa_stream_get_state: unsafe extern "C" fn(*mut PaStream) -> c_int,
+    pa_stream_peek: unsafe extern "C" fn(*mut PaStream, *mut *const c_void, *mut usize) -> c_int,
+    pa_stream_drop: unsafe extern "C" fn(*mut PaStream) -> c_int,
+    pa_stream_disconnect: unsafe extern "C" fn(*mut PaStream) -> c_int,
+    pa_stream_unref: unsafe extern "C" fn(*mut PaStream),
+    pa_proplist_gets: unsafe extern "C" fn(*const PaProplist, *const c_char) -> *const c_char,
+    pa_strerror: unsafe extern "C" fn(c_int) -> *const c_char,
+}
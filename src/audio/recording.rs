@@ -0,0 +1,230 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Recordings start with this, followed by the version (u16), bar count (u32),
+/// framerate (u32) and value format (u8), all little-endian. Every frame is a
+/// u64 timestamp in microseconds since the start of the recording followed by
+/// one value per bar.
+pub const MAGIC: &[u8; 8] = b"WCAVAREC";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 19;
+// Values are u16, 65535 being a full bar.
+const FORMAT_16BIT: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordingHeader {
+    pub bar_count: u32,
+    pub framerate: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedFrame {
+    pub timestamp: Duration,
+    pub bar_values: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub header: RecordingHeader,
+    pub frames: Vec<RecordedFrame>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Recording {
+    pub fn load(filename: &str) -> io::Result<Self> {
+        Self::parse(&fs::read(filename)?)
+            .map_err(|error| invalid_data(format!("{}: {}", filename, error)))
+    }
+
+    /// A partial frame at the end, e.g. from a recorder that got killed, is ignored.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if !data.starts_with(MAGIC) || data.len() < HEADER_SIZE {
+            return Err(invalid_data("Not a wallpaper-cava recording".into()));
+        }
+        let version = u16::from_le_bytes([data[8], data[9]]);
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported recording version {}",
+                version
+            )));
+        }
+        let header = RecordingHeader {
+            bar_count: u32::from_le_bytes(data[10..14].try_into().unwrap()),
+            framerate: u32::from_le_bytes(data[14..18].try_into().unwrap()),
+        };
+        if data[18] != FORMAT_16BIT {
+            return Err(invalid_data(format!(
+                "Unsupported recording format {}",
+                data[18]
+            )));
+        }
+        let frame_size = 8 + header.bar_count as usize * 2;
+        let frames = data[HEADER_SIZE..]
+            .chunks_exact(frame_size)
+            .map(|frame| RecordedFrame {
+                timestamp: Duration::from_micros(u64::from_le_bytes(
+                    frame[..8].try_into().unwrap(),
+                )),
+                bar_values: frame[8..]
+                    .chunks_exact(2)
+                    .map(|value| u16::from_le_bytes([value[0], value[1]]) as f32 / 65535.0)
+                    .collect(),
+            })
+            .collect();
+        Ok(Recording { header, frames })
+    }
+
+    pub fn is_recording(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+}
+
+/// Appends every frame it's given to a recording file. Frames are written
+/// unbuffered, so the file stays usable when wallpaper-cava is killed.
+pub struct Recorder {
+    file: File,
+    bar_count: usize,
+    started_at: Instant,
+    frame_buffer: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn create(filename: &str, header: RecordingHeader) -> io::Result<Self> {
+        let mut file = File::create(filename)?;
+        let mut header_data = Vec::with_capacity(HEADER_SIZE);
+        header_data.extend_from_slice(MAGIC);
+        header_data.extend_from_slice(&VERSION.to_le_bytes());
+        header_data.extend_from_slice(&header.bar_count.to_le_bytes());
+        header_data.extend_from_slice(&header.framerate.to_le_bytes());
+        header_data.push(FORMAT_16BIT);
+        file.write_all(&header_data)?;
+        Ok(Recorder {
+            file,
+            bar_count: header.bar_count as usize,
+            started_at: Instant::now(),
+            frame_buffer: Vec::new(),
+        })
+    }
+
+    pub fn write_frame(&mut self, bar_values: &[f32]) -> io::Result<()> {
+        let timestamp = self.started_at.elapsed().as_micros() as u64;
+        self.frame_buffer.clear();
+        self.frame_buffer
+            .extend_from_slice(&timestamp.to_le_bytes());
+        // Always bar_count values, even if bars.amount changed with a reload.
+        for index in 0..self.bar_count {
+            let value = bar_values.get(index).copied().unwrap_or(0.0);
+            let value = (value.clamp(0.0, 1.0) * 65535.0).round() as u16;
+            self.frame_buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        self.file.write_all(&self.frame_buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes(version: u16, bar_count: u32, format: u8) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&version.to_le_bytes());
+        data.extend_from_slice(&bar_count.to_le_bytes());
+        data.extend_from_slice(&60u32.to_le_bytes());
+        data.push(format);
+        data
+    }
+
+    fn frame_bytes(timestamp: u64, values: &[u16]) -> Vec<u8> {
+        let mut data = timestamp.to_le_bytes().to_vec();
+        for value in values {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn reads_what_the_recorder_wrote() {
+        let filename = std::env::temp_dir().join(format!(
+            "wallpaper-cava-recording-{}.rec",
+            std::process::id()
+        ));
+        let filename = filename.to_str().unwrap();
+        let header = RecordingHeader {
+            bar_count: 3,
+            framerate: 60,
+        };
+        let mut recorder = Recorder::create(filename, header).unwrap();
+        recorder.write_frame(&[0.0, 0.5, 1.0]).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        // Out of range and missing values are clamped and padded.
+        recorder.write_frame(&[1.5, -1.0]).unwrap();
+        drop(recorder);
+
+        let recording = Recording::load(filename).unwrap();
+        fs::remove_file(filename).unwrap();
+        assert_eq!(recording.header, header);
+        assert_eq!(recording.frames.len(), 2);
+        assert_eq!(
+            recording.frames[0].bar_values,
+            [0.0, 32768.0 / 65535.0, 1.0]
+        );
+        assert_eq!(recording.frames[1].bar_values, [1.0, 0.0, 0.0]);
+        let elapsed = recording.frames[1].timestamp - recording.frames[0].timestamp;
+        assert!(elapsed >= Duration::from_millis(20), "{:?}", elapsed);
+        assert!(recording.frames[0].timestamp < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn parses_timestamps_and_values() {
+        let mut data = header_bytes(VERSION, 2, FORMAT_16BIT);
+        data.extend(frame_bytes(0, &[0, 65535]));
+        data.extend(frame_bytes(16_667, &[13107, 52428]));
+        assert!(Recording::is_recording(&data));
+        let recording = Recording::parse(&data).unwrap();
+        assert_eq!(
+            recording.header,
+            RecordingHeader {
+                bar_count: 2,
+                framerate: 60
+            }
+        );
+        assert_eq!(recording.frames[1].timestamp, Duration::from_micros(16_667));
+        assert_eq!(recording.frames[1].bar_values, [0.2, 0.8]);
+    }
+
+    #[test]
+    fn ignores_a_truncated_last_frame() {
+        let mut data = header_bytes(VERSION, 2, FORMAT_16BIT);
+        data.extend(frame_bytes(0, &[1, 2]));
+        data.extend(&frame_bytes(10, &[3, 4])[..9]);
+        assert_eq!(Recording::parse(&data).unwrap().frames.len(), 1);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut bad_magic = header_bytes(VERSION, 2, FORMAT_16BIT);
+        bad_magic[0] = b'X';
+        assert!(!Recording::is_recording(&bad_magic));
+        let truncated_header = &header_bytes(VERSION, 2, FORMAT_16BIT)[..HEADER_SIZE - 1];
+        for (data, error) in [
+            (bad_magic.as_slice(), "Not a wallpaper-cava recording"),
+            (truncated_header, "Not a wallpaper-cava recording"),
+            (
+                &header_bytes(2, 2, FORMAT_16BIT),
+                "Unsupported recording version 2",
+            ),
+            (
+                &header_bytes(VERSION, 2, 8),
+                "Unsupported recording format 8",
+            ),
+        ] {
+            let parse_error = Recording::parse(data).unwrap_err();
+            assert_eq!(parse_error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(parse_error.to_string(), error);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use super::recording::Recording;
+use super::{AudioSource, FrameSlot};
+use crate::app_config::Config;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Plays back a file written with `--record` at its original pace, scaled by
+/// `audio.speed`. With `audio.loop` it starts over at the end, otherwise the
+/// last frame stays.
+pub struct ReplaySource {
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+struct Player {
+    recording: Recording,
+    looping: bool,
+    speed: f32,
+    stopping: Arc<AtomicBool>,
+    frame_slot: FrameSlot,
+}
+
+impl ReplaySource {
+    /// `on_frame` is called from the player thread for every frame. Fails if the
+    /// recording can't be read or its bar count differs from `bars.amount`.
+    pub fn spawn(
+        config: &Config,
+        on_frame: impl Fn(&[f32]) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let filename = config.audio.file.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The replay backend needs audio.file",
+            )
+        })?;
+        let recording = Recording::load(filename)?;
+        if recording.header.bar_count != config.bars.amount {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} has {} bars, but bars.amount is {}",
+                    filename, recording.header.bar_count, config.bars.amount
+                ),
+            ));
+        }
+        let speed = config.audio.speed.unwrap_or(1.0);
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "audio.speed must be positive",
+            ));
+        }
+        let stopping = Arc::new(AtomicBool::new(false));
+        let frame_slot = FrameSlot::new(config.bars.amount as usize, on_frame);
+        let player = Player {
+            recording,
+            looping: config.audio.looping.unwrap_or(false),
+            speed,
+            stopping: stopping.clone(),
+            frame_slot: frame_slot.clone(),
+        };
+        thread::Builder::new()
+            .name("replay".into())
+            .spawn(move || player.run())?;
+        Ok(ReplaySource {
+            stopping,
+            frame_slot,
+        })
+    }
+}
+
+impl AudioSource for ReplaySource {
+    fn take_frame(&self, bar_values: &mut [f32]) -> bool {
+        self.frame_slot.take(bar_values)
+    }
+
+    fn shutdown(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ReplaySource {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Player {
+    fn run(self) {
+        if self.recording.frames.is_empty() {
+            eprintln!("The recording holds no frames");
+            return;
+        }
+        let first_timestamp = self.recording.frames[0].timestamp;
+        loop {
+            let started_at = Instant::now();
+            for frame in &self.recording.frames {
+                let due = frame
+                    .timestamp
+                    .saturating_sub(first_timestamp)
+                    .div_f32(self.speed);
+                if let Some(wait) = due.checked_sub(started_at.elapsed()) {
+                    thread::sleep(wait);
+                }
+                if self.stopping.load(Ordering::SeqCst) {
+                    return;
+                }
+                self.frame_slot.publish(&frame.bar_values);
+            }
+            if !self.looping {
+                return;
+            }
+            // Keep the pause between the last and the first frame one frame long.
+            let framerate = self.recording.header.framerate.max(1);
+            thread::sleep((Duration::from_secs(1) / framerate).div_f32(self.speed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::recording::MAGIC;
+    use std::fs;
+    use std::sync::Mutex;
+
+    // Three frames 100ms apart, the first bar holds the frame's index.
+    fn write_recording(name: &str) -> String {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&10u32.to_le_bytes());
+        data.push(16);
+        for index in 0..3u16 {
+            data.extend_from_slice(&(index as u64 * 100_000).to_le_bytes());
+            data.extend_from_slice(&(index * 10000).to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+        }
+        let filename = std::env::temp_dir().join(format!(
+            "wallpaper-cava-replay-{}-{}.rec",
+            name,
+            std::process::id()
+        ));
+        fs::write(&filename, data).unwrap();
+        filename.to_str().unwrap().to_string()
+    }
+
+    fn config(file: &str, looping: bool, speed: f32) -> Config {
+        toml::from_str(&format!(
+            "[general]\nframerate = 10\nbackground_color = '#000000'\n[bars]\namount = 2\ngap = 0.1\n\
+             [colors]\ncolor_1 = '#ffffff'\n[smoothing]\n\
+             [audio]\nbackend = \"replay\"\nfile = \"{}\"\nloop = {}\nspeed = {}\n",
+            file, looping, speed
+        ))
+        .unwrap()
+    }
+
+    // Frame indices in the order they were played and when.
+    fn play(config: &Config, duration: Duration) -> Vec<(u16, Duration)> {
+        let start = Instant::now();
+        let played = Arc::new(Mutex::new(Vec::new()));
+        let source = ReplaySource::spawn(config, {
+            let played = played.clone();
+            move |bar_values: &[f32]| {
+                let index = (bar_values[0] * 65535.0 / 10000.0).round() as u16;
+                played.lock().unwrap().push((index, start.elapsed()));
+            }
+        })
+        .unwrap();
+        thread::sleep(duration);
+        source.shutdown();
+        let played = played.lock().unwrap().clone();
+        played
+    }
+
+    #[test]
+    fn plays_once_at_the_recorded_pace() {
+        let file = write_recording("once");
+        let played = play(&config(&file, false, 1.0), Duration::from_millis(500));
+        fs::remove_file(file).unwrap();
+        let indices: Vec<u16> = played.iter().map(|(index, _)| *index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+        assert!(played[2].1 - played[0].1 >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn speed_scales_the_pace() {
+        let file = write_recording("speed");
+        let played = play(&config(&file, false, 4.0), Duration::from_millis(300));
+        fs::remove_file(file).unwrap();
+        assert_eq!(played.len(), 3);
+        let elapsed = played[2].1 - played[0].1;
+        assert!(elapsed >= Duration::from_millis(50), "{:?}", elapsed);
+        // 200ms at the recorded pace.
+        assert!(elapsed < Duration::from_millis(150), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn loops_with_a_frame_long_pause() {
+        let file = write_recording("loop");
+        let played = play(&config(&file, true, 2.0), Duration::from_millis(500));
+        fs::remove_file(file).unwrap();
+        let indices: Vec<u16> = played.iter().map(|(index, _)| *index).collect();
+        // Every round takes 100ms plus a 50ms pause.
+        assert!(indices.len() >= 7, "{:?}", indices);
+        for (position, index) in indices.iter().enumerate() {
+            assert_eq!(*index as usize, position % 3);
+        }
+        let pause = played[3].1 - played[2].1;
+        assert!(pause >= Duration::from_millis(50), "{:?}", pause);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let file = write_recording("invalid");
+        assert!(ReplaySource::spawn(&config(&file, false, 0.0), |_: &[f32]| {}).is_err());
+        let mut config = config(&file, false, 1.0);
+        config.bars.amount = 3;
+        assert!(ReplaySource::spawn(&config, |_: &[f32]| {}).is_err());
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use crate::app_config::{
+    array_from_config_color, expand_home, BackgroundConfig, BackgroundFit, Config,
+};
+use crate::appearance::sample_gradient;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+/// The layer drawn over `general.background_color` and under the bars:
+/// `background.gradient` with `background.image` on top.
+pub struct Background {
+    gradient: Vec<[f32; 4]>,
+    image: Option<RgbaImage>,
+    fit: BackgroundFit,
+    // Composed ahead of time by `prepare`.
+    composed: Option<RgbaImage>,
+}
+
+impl Background {
+    /// Decodes `background.image`, None when there is nothing to draw.
+    pub fn load(config: &Config) -> Result<Option<Self>, String> {
+        let image = config.background.image.as_deref().map(expand_home);
+        Background::with_image(config, image.as_deref())
+    }
+
+    /// Like `load`, with `image` in place of `background.image`.
+    pub fn with_image(config: &Config, image: Option<&Path>) -> Result<Option<Self>, String> {
+        let image = image.map(decode).transpose()?;
+        Ok(Background::from_image(&config.background, image))
+    }
+
+    /// Like `with_image`, for an image that is already decoded.
+    pub fn from_image(background: &BackgroundConfig, image: Option<RgbaImage>) -> Option<Self> {
+        let gradient: Vec<[f32; 4]> = background
+            .gradient
+            .iter()
+            .flatten()
+            .map(|color| array_from_config_color(color.clone()))
+            .collect();
+        if gradient.is_empty() && image.is_none() {
+            return None;
+        }
+        Some(Background {
+            gradient,
+            image,
+            fit: background.fit,
+            composed: None,
+        })
+    }
+
+    /// Composes the layer for an output of this size ahead of time, off the
+    /// thread that draws, `compose` copies it from then on.
+    pub fn prepare(&mut self, width: u32, height: u32) {
+        self.composed = Some(self.compose(width, height));
+    }
+
+    /// Renders the layer for an output of this size, rows top to bottom.
+    pub fn compose(&self, width: u32, height: u32) -> RgbaImage {
+        if let Some(composed) = &self.composed {
+            if composed.dimensions() == (width, height) {
+                return composed.clone();
+            }
+        }
+        let mut canvas = RgbaImage::new(width, height);
+        if !self.gradient.is_empty() {
+            for (row, pixels) in canvas.rows_mut().enumerate() {
+                // Sampled at the pixel center like the bar gradient.
+                let position = (height as usize - row) as f32 - 0.5;
+                let color = to_rgba8(sample_gradient(&self.gradient, position / height as f32));
+                for pixel in pixels {
+                    *pixel = color;
+                }
+            }
+        }
+        let Some(image) = &self.image else {
+            return canvas;
+        };
+        let (image_width, image_height) = (image.width() as f32, image.height() as f32);
+        let scale = match self.fit {
+            BackgroundFit::Fill => (width as f32 / image_width).max(height as f32 / image_height),
+            BackgroundFit::Fit => (width as f32 / image_width).min(height as f32 / image_height),
+            BackgroundFit::Center | BackgroundFit::Tile => 1.0,
+        };
+        if self.fit == BackgroundFit::Tile {
+            imageops::tile(&mut canvas, image);
+            return canvas;
+        }
+        let scaled_width = ((image_width * scale).round() as u32).max(1);
+        let scaled_height = ((image_height * scale).round() as u32).max(1);
+        let x = (width as i64 - scaled_width as i64) / 2;
+        let y = (height as i64 - scaled_height as i64) / 2;
+        if scaled_width == image.width() && scaled_height == image.height() {
+            imageops::overlay(&mut canvas, image, x, y);
+        } else {
+            let scaled =
+                imageops::resize(image, scaled_width, scaled_height, FilterType::CatmullRom);
+            imageops::overlay(&mut canvas, &scaled, x, y);
+        }
+        canvas
+    }
+}
+
+/// Decodes the image at `path` for `Background::from_image`.
+pub fn decode(path: &Path) -> Result<RgbaImage, String> {
+    Ok(image::open(path)
+        .map_err(|error| format!("Unable to read {}: {}", path.display(), error))?
+        .to_rgba8())
+}
+
+fn to_rgba8(color: [f32; 4]) -> Rgba<u8> {
+    Rgba(color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    // Red on the left, blue on the right.
+    fn background(fit: BackgroundFit) -> Background {
+        Background {
+            gradient: Vec::new(),
+            image: Some(RgbaImage::from_fn(
+                2,
+                1,
+                |x, _| if x == 0 { RED } else { BLUE },
+            )),
+            fit,
+            composed: None,
+        }
+    }
+
+    fn is_reddish(pixel: &Rgba<u8>) -> bool {
+        pixel[0] > 200 && pixel[2] < 55 && pixel[3] == 255
+    }
+
+    fn is_bluish(pixel: &Rgba<u8>) -> bool {
+        pixel[2] > 200 && pixel[0] < 55 && pixel[3] == 255
+    }
+
+    #[test]
+    fn fit_letterboxes() {
+        // Scaled up to 4x2, centered vertically.
+        let canvas = background(BackgroundFit::Fit).compose(4, 4);
+        for x in 0..4 {
+            assert_eq!(*canvas.get_pixel(x, 0), CLEAR);
+            assert_eq!(*canvas.get_pixel(x, 3), CLEAR);
+        }
+        for y in 1..3 {
+            assert!(is_reddish(canvas.get_pixel(0, y)));
+            assert!(is_bluish(canvas.get_pixel(3, y)));
+        }
+    }
+
+    #[test]
+    fn fill_crops() {
+        // Scaled up to 4x2 with a column cut off on either side.
+        let canvas = background(BackgroundFit::Fill).compose(2, 2);
+        assert!(canvas.pixels().all(|pixel| pixel[3] == 255));
+        for y in 0..2 {
+            assert!(canvas.get_pixel(0, y)[0] > canvas.get_pixel(0, y)[2]);
+            assert!(canvas.get_pixel(1, y)[2] > canvas.get_pixel(1, y)[0]);
+        }
+    }
+
+    #[test]
+    fn center_keeps_the_size() {
+        let canvas = background(BackgroundFit::Center).compose(4, 3);
+        for (x, y, pixel) in canvas.enumerate_pixels() {
+            let expected = match (x, y) {
+                (1, 1) => RED,
+                (2, 1) => BLUE,
+                _ => CLEAR,
+            };
+            assert_eq!(*pixel, expected, "at {}x{}", x, y);
+        }
+    }
+
+    #[test]
+    fn tile_repeats() {
+        let canvas = background(BackgroundFit::Tile).compose(5, 2);
+        for (x, _, pixel) in canvas.enumerate_pixels() {
+            assert_eq!(*pixel, if x % 2 == 0 { RED } else { BLUE });
+        }
+    }
+
+    #[test]
+    fn gradient_runs_bottom_to_top() {
+        let background = Background {
+            gradient: vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]],
+            image: None,
+            fit: BackgroundFit::default(),
+            composed: None,
+        };
+        let canvas = background.compose(1, 4);
+        // Pixel centers, 7/8 and 1/8 of the way up.
+        assert_eq!(*canvas.get_pixel(0, 0), Rgba([32, 0, 223, 255]));
+        assert_eq!(*canvas.get_pixel(0, 3), Rgba([223, 0, 32, 255]));
+        assert!(canvas.get_pixel(0, 1)[2] > canvas.get_pixel(0, 2)[2]);
+    }
+
+    #[test]
+    fn prepared_layers_match_the_size() {
+        let mut prepared = background(BackgroundFit::Fit);
+        prepared.prepare(4, 4);
+        assert_eq!(
+            prepared.compose(4, 4),
+            background(BackgroundFit::Fit).compose(4, 4)
+        );
+        assert_eq!(prepared.compose(2, 2).dimensions(), (2, 2));
+    }
+}
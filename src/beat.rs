@@ -0,0 +1,314 @@
+use crate::app_config::BeatConfig;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Flux below this is noise, however quiet the music was before.
+const MIN_FLUX: f32 = 0.02;
+// Onsets closer together than this count as one beat, that's 300 BPM.
+const MIN_BEAT_INTERVAL: Duration = Duration::from_millis(200);
+// A longer gap between beats starts the tempo estimate over.
+const MAX_BEAT_INTERVAL: Duration = Duration::from_secs(2);
+// The tempo is the median of the intervals between this many beats.
+const TEMPO_BEATS: usize = 9;
+const DEFAULT_DECAY: f32 = 0.2;
+// An effect below this level is invisible.
+const SETTLED_LEVEL: f32 = 0.001;
+
+/// Finds kicks in decoded frames. The spectral flux, the summed rise of the
+/// lowest `beat.bass_bars` bars since the last frame, is an onset when it
+/// exceeds the mean of the last second by `beat.sensitivity` standard deviations.
+pub struct BeatDetector {
+    bass_bars: usize,
+    sensitivity: f32,
+    previous: Vec<f32>,
+    flux_history: VecDeque<f32>,
+    history_size: usize,
+    beats: VecDeque<Instant>,
+}
+
+impl BeatDetector {
+    /// `framerate` is the rate audio frames arrive at.
+    pub fn new(config: &BeatConfig, bar_count: usize, framerate: u32) -> Self {
+        let bass_bars = match config.bass_bars {
+            Some(bass_bars) => bass_bars as usize,
+            None => bar_count / 8,
+        }
+        .clamp(1, bar_count.max(1));
+        BeatDetector {
+            bass_bars,
+            sensitivity: config.sensitivity.unwrap_or(1.5),
+            previous: vec![0.0; bass_bars],
+            flux_history: VecDeque::new(),
+            history_size: framerate.max(1) as usize,
+            beats: VecDeque::new(),
+        }
+    }
+
+    /// Feeds the frame that arrived at `now`, returns whether it starts a beat.
+    pub fn process(&mut self, bar_values: &[f32], now: Instant) -> bool {
+        let bass = &bar_values[..self.bass_bars.min(bar_values.len())];
+        let flux = bass
+            .iter()
+            .zip(&self.previous)
+            .map(|(value, previous)| (value - previous).max(0.0))
+            .sum::<f32>()
+            / self.bass_bars as f32;
+        self.previous[..bass.len()].copy_from_slice(bass);
+
+        let count = self.flux_history.len().max(1) as f32;
+        let mean = self.flux_history.iter().sum::<f32>() / count;
+        let variance = self
+            .flux_history
+            .iter()
+            .map(|flux| (flux - mean).powi(2))
+            .sum::<f32>()
+            / count;
+        if self.flux_history.len() == self.history_size {
+            self.flux_history.pop_front();
+        }
+        self.flux_history.push_back(flux);
+        if flux < MIN_FLUX || flux <= mean + self.sensitivity * variance.sqrt() {
+            return false;
+        }
+
+        if let Some(last_beat) = self.beats.back() {
+            let interval = now.saturating_duration_since(*last_beat);
+            if interval < MIN_BEAT_INTERVAL {
+                return false;
+            }
+            if interval > MAX_BEAT_INTERVAL {
+                self.beats.clear();
+            }
+        }
+        if self.beats.len() == TEMPO_BEATS {
+            self.beats.pop_front();
+        }
+        self.beats.push_back(now);
+        true
+    }
+
+    /// Tempo from the recent beats, folded into 60..180 BPM. None until four
+    /// beats in a row were found or when the last one is too long ago.
+    pub fn bpm(&self, now: Instant) -> Option<f32> {
+        let last_beat = self.beats.back()?;
+        if self.beats.len() < 4 || now.saturating_duration_since(*last_beat) > MAX_BEAT_INTERVAL {
+            return None;
+        }
+        let mut intervals: Vec<f32> = self
+            .beats
+            .iter()
+            .zip(self.beats.iter().skip(1))
+            .map(|(first, second)| second.duration_since(*first).as_secs_f32())
+            .collect();
+        intervals.sort_by(f32::total_cmp);
+        let mut bpm = 60.0 / intervals[intervals.len() / 2];
+        while bpm < 60.0 {
+            bpm *= 2.0;
+        }
+        while bpm >= 180.0 {
+            bpm /= 2.0;
+        }
+        Some(bpm)
+    }
+}
+
+/// How strongly each effect shows right now, 0 when off.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EffectLevels {
+    /// Mixes the background towards white.
+    pub flash: f32,
+    /// Makes the bars taller by this fraction.
+    pub bump: f32,
+    /// Moves the gradient up by this fraction of the height.
+    pub gradient_shift: f32,
+    /// Mixes the bar colors towards white.
+    pub glow: f32,
+}
+
+/// Jumps to its strength on every beat and decays exponentially, `decay`
+/// being the time constant in seconds.
+struct Envelope {
+    strength: f32,
+    decay: f32,
+}
+
+impl Envelope {
+    fn new(strength: Option<f32>, decay: Option<f32>) -> Self {
+        Envelope {
+            strength: strength.unwrap_or(0.0),
+            decay: decay.unwrap_or(DEFAULT_DECAY).max(0.001),
+        }
+    }
+
+    fn level(&self, since_beat: f32) -> f32 {
+        self.strength * (-since_beat / self.decay).exp()
+    }
+}
+
+/// The visual effects triggered by beats, each off unless its strength is set.
+pub struct BeatEffects {
+    flash: Envelope,
+    bump: Envelope,
+    gradient_shift: Envelope,
+    glow: Envelope,
+    last_beat: Option<Instant>,
+}
+
+impl BeatEffects {
+    pub fn new(config: &BeatConfig) -> Self {
+        BeatEffects {
+            flash: Envelope::new(config.flash, config.flash_decay),
+            bump: Envelope::new(config.bump, config.bump_decay),
+            gradient_shift: Envelope::new(config.gradient_shift, config.gradient_shift_decay),
+            glow: Envelope::new(config.glow, config.glow_decay),
+            last_beat: None,
+        }
+    }
+
+    pub fn beat(&mut self, now: Instant) {
+        self.last_beat = Some(now);
+    }
+
+    pub fn levels(&self, now: Instant) -> EffectLevels {
+        let Some(last_beat) = self.last_beat else {
+            return EffectLevels::default();
+        };
+        let since_beat = now.saturating_duration_since(last_beat).as_secs_f32();
+        EffectLevels {
+            flash: self.flash.level(since_beat),
+            bump: self.bump.level(since_beat),
+            gradient_shift: self.gradient_shift.level(since_beat),
+            glow: self.glow.level(since_beat),
+        }
+    }
+
+    /// Whether drawing again at `now` would show different effects.
+    pub fn is_active(&self, now: Instant) -> bool {
+        let levels = self.levels(now);
+        [
+            levels.flash,
+            levels.bump,
+            levels.gradient_shift,
+            levels.glow,
+        ]
+        .iter()
+        .any(|level| level.abs() > SETTLED_LEVEL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMERATE: u32 = 60;
+
+    fn frame_time(start: Instant, frame: usize) -> Instant {
+        start + Duration::from_secs(1) / FRAMERATE * frame as u32
+    }
+
+    // 16 bars, the lowest 2 kick every `kick_every` frames and fade out, the
+    // rest holds steady noise.
+    fn kick_frames(kick_every: usize, frame_count: usize) -> Vec<Vec<f32>> {
+        let mut bass = 0.0;
+        (0..frame_count)
+            .map(|frame| {
+                bass = if frame % kick_every == 0 {
+                    1.0
+                } else {
+                    bass * 0.7
+                };
+                let mut bar_values = vec![0.3; 16];
+                bar_values[0] = bass;
+                bar_values[1] = bass * 0.8;
+                bar_values
+            })
+            .collect()
+    }
+
+    fn detected_beats(
+        detector: &mut BeatDetector,
+        frames: &[Vec<f32>],
+        start: Instant,
+    ) -> Vec<usize> {
+        frames
+            .iter()
+            .enumerate()
+            .filter(|(frame, bar_values)| detector.process(bar_values, frame_time(start, *frame)))
+            .map(|(frame, _)| frame)
+            .collect()
+    }
+
+    #[test]
+    fn steady_input_has_no_beats() {
+        let mut detector = BeatDetector::new(&BeatConfig::default(), 16, FRAMERATE);
+        let start = Instant::now();
+        let frames = vec![vec![0.0; 16]; 120];
+        assert!(detected_beats(&mut detector, &frames, start).is_empty());
+        assert_eq!(detector.bpm(frame_time(start, 120)), None);
+    }
+
+    #[test]
+    fn finds_kicks_and_their_tempo() {
+        let mut detector = BeatDetector::new(&BeatConfig::default(), 16, FRAMERATE);
+        let start = Instant::now();
+        // 120 BPM is a kick every 30 frames.
+        let frames = kick_frames(30, 240);
+        let beats = detected_beats(&mut detector, &frames, start);
+        assert_eq!(beats, vec![0, 30, 60, 90, 120, 150, 180, 210]);
+        let bpm = detector.bpm(frame_time(start, 240)).unwrap();
+        assert!((bpm - 120.0).abs() < 0.5, "{}", bpm);
+        // The tempo is forgotten once the kicks stop.
+        assert_eq!(
+            detector.bpm(frame_time(start, 240 + 3 * FRAMERATE as usize)),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_everything_above_the_bass() {
+        let mut detector = BeatDetector::new(&BeatConfig::default(), 16, FRAMERATE);
+        let frames: Vec<Vec<f32>> = kick_frames(30, 120)
+            .into_iter()
+            .map(|mut bar_values| {
+                bar_values.rotate_right(8);
+                bar_values
+            })
+            .collect();
+        // Only the steady bars coming up from silence in the first frame count.
+        assert_eq!(
+            detected_beats(&mut detector, &frames, Instant::now()),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn merges_onsets_closer_than_the_minimum_interval() {
+        let mut detector = BeatDetector::new(&BeatConfig::default(), 16, FRAMERATE);
+        // A kick every 9 frames is 400 BPM, only every second one is far enough apart.
+        let frames = kick_frames(9, 60);
+        let beats = detected_beats(&mut detector, &frames, Instant::now());
+        assert_eq!(beats, vec![0, 18, 36, 54]);
+    }
+
+    #[test]
+    fn effects_decay_after_a_beat() {
+        let config = BeatConfig {
+            flash: Some(0.5),
+            flash_decay: Some(0.1),
+            bump: Some(0.2),
+            ..Default::default()
+        };
+        let mut effects = BeatEffects::new(&config);
+        let start = Instant::now();
+        assert!(!effects.is_active(start));
+        effects.beat(start);
+        let levels = effects.levels(start);
+        assert_eq!(levels.flash, 0.5);
+        assert_eq!(levels.bump, 0.2);
+        assert_eq!(levels.glow, 0.0);
+        let levels = effects.levels(start + Duration::from_millis(100));
+        assert!((levels.flash - 0.5 * (-1.0f32).exp()).abs() < 1e-5);
+        assert!((levels.bump - 0.2 * (-0.5f32).exp()).abs() < 1e-5);
+        assert!(!effects.is_active(start + Duration::from_secs(2)));
+    }
+}
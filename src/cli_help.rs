@@ -1,4 +1,28 @@
 pub fn print_help() {
     println!("Command line options");
     println!("--config path");
+    println!("--preset name  apply [preset.name] instead of general.preset");
+    println!("--record path  write every frame to a recording, see audio.backend = \"replay\"");
+    println!();
+    println!("Control a running instance through $XDG_RUNTIME_DIR/wallpaper-cava.sock");
+    println!("msg command  prints the reply as JSON, fails if the command did");
+    println!("    pause, resume, toggle-pause");
+    println!("    show, hide, toggle");
+    println!(
+        "    sensitivity value  restarts the audio source with general.sensitivity set to value"
+    );
+    println!("    colors #RRGGBB...  replaces the gradient colors, bottom to top");
+    println!("    preset name        switches to a preset, cross-fading if only colors or bars.gap change");
+    println!("    reload             reads the config file again with the current preset, except for render.backend and general.preferred_output");
+    println!("    next               shows the next image of background.directory");
+    println!("    state              reports the current state");
+    println!("Signals: SIGHUP reloads, SIGUSR1 toggles visibility, SIGUSR2 toggles pause");
+    println!();
+    println!("Offscreen rendering");
+    println!(
+        "render --frames path --out path [--config path] [--preset name] [--size WIDTHxHEIGHT] [--frame index]"
+    );
+    println!("    --frames  recording or file with cava's raw 16bit output, bars.amount values per frame");
+    println!("    --size    defaults to 1920x1080");
+    println!("    --frame   index of the frame to render, defaults to the last one");
 }
@@ -0,0 +1,214 @@
+use crate::ipc::{Command, Reply};
+use calloop::channel::{self, Event};
+use calloop::LoopHandle;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+pub const SERVICE_NAME: &str = "org.wallpapercava.Visualizer";
+pub const OBJECT_PATH: &str = "/org/wallpapercava/Visualizer";
+
+// Fps reads 0 once it wasn't updated for this long, nothing is being drawn then.
+const FPS_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A method call waiting for the event loop to run its command.
+struct Request {
+    command: Command,
+    reply: async_channel::Sender<Reply>,
+}
+
+#[derive(Debug, Clone)]
+struct Properties {
+    visible: bool,
+    preset: String,
+    fps: f64,
+    fps_updated: Instant,
+}
+
+struct Visualizer {
+    requests: channel::Sender<Request>,
+    properties: Arc<Mutex<Properties>>,
+}
+
+impl Visualizer {
+    async fn run(&self, command: Command) -> fdo::Result<()> {
+        let (reply_sender, reply_receiver) = async_channel::bounded(1);
+        self.requests
+            .send(Request {
+                command,
+                reply: reply_sender,
+            })
+            .map_err(|_| fdo::Error::Failed("The visualizer is shutting down".into()))?;
+        let reply = reply_receiver
+            .recv()
+            .await
+            .map_err(|_| fdo::Error::Failed("The visualizer is shutting down".into()))?;
+        match reply.error {
+            Some(error) => Err(fdo::Error::Failed(error)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[zbus::interface(name = "org.wallpapercava.Visualizer")]
+impl Visualizer {
+    async fn pause(&self) -> fdo::Result<()> {
+        self.run(Command::Pause).await
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        self.run(Command::Resume).await
+    }
+
+    async fn reload(&self) -> fdo::Result<()> {
+        self.run(Command::Reload).await
+    }
+
+    async fn next(&self) -> fdo::Result<()> {
+        self.run(Command::Next).await
+    }
+
+    async fn set_preset(&self, name: String) -> fdo::Result<()> {
+        self.run(Command::Preset(name)).await
+    }
+
+    async fn set_sensitivity(&self, sensitivity: f64) -> fdo::Result<()> {
+        if sensitivity.is_nan() || sensitivity <= 0.0 {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Invalid sensitivity: {}",
+                sensitivity
+            )));
+        }
+        self.run(Command::Sensitivity(sensitivity as f32)).await
+    }
+
+    #[zbus(property)]
+    fn visible(&self) -> bool {
+        self.properties.lock().unwrap().visible
+    }
+
+    /// Empty without a preset.
+    #[zbus(property)]
+    fn current_preset(&self) -> String {
+        self.properties.lock().unwrap().preset.clone()
+    }
+
+    /// Frames drawn during the last second.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn fps(&self) -> f64 {
+        let properties = self.properties.lock().unwrap();
+        if properties.fps_updated.elapsed() > FPS_TIMEOUT {
+            return 0.0;
+        }
+        properties.fps
+    }
+
+    /// `bpm` is 0 until the tempo is known.
+    #[zbus(signal)]
+    async fn beat(emitter: &SignalEmitter<'_>, bpm: f64) -> zbus::Result<()>;
+}
+
+/// Owns `org.wallpapercava.Visualizer` on the session bus. Method calls run
+/// `handler` on the event loop like commands from the control socket.
+pub struct DbusService {
+    connection: Connection,
+    properties: Arc<Mutex<Properties>>,
+}
+
+impl DbusService {
+    /// Connects to the session bus, or the bus at `address` if given.
+    pub fn start<'l, D: 'l>(
+        handle: &LoopHandle<'l, D>,
+        address: Option<&str>,
+        mut handler: impl FnMut(Command, &mut D) -> Reply + 'l,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (requests, request_channel) = channel::channel::<Request>();
+        handle
+            .insert_source(request_channel, move |event, _, state| {
+                if let Event::Msg(request) = event {
+                    let reply = handler(request.command, state);
+                    let _ = request.reply.try_send(reply);
+                }
+            })
+            .map_err(|error| error.to_string())?;
+        let properties = Arc::new(Mutex::new(Properties {
+            visible: true,
+            preset: String::new(),
+            fps: 0.0,
+            fps_updated: Instant::now(),
+        }));
+        let visualizer = Visualizer {
+            requests,
+            properties: properties.clone(),
+        };
+        let builder = match address {
+            Some(address) => Builder::address(address)?,
+            None => Builder::session()?,
+        };
+        let connection = builder
+            .name(SERVICE_NAME)?
+            .serve_at(OBJECT_PATH, visualizer)?
+            .build()?;
+        Ok(DbusService {
+            connection,
+            properties,
+        })
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        let mut properties = self.properties.lock().unwrap();
+        if properties.visible != visible {
+            properties.visible = visible;
+            drop(properties);
+            self.emit_changed(|visualizer, emitter| {
+                zbus::block_on(visualizer.visible_changed(emitter))
+            });
+        }
+    }
+
+    pub fn set_preset(&self, preset: Option<&str>) {
+        let preset = preset.unwrap_or_default();
+        let mut properties = self.properties.lock().unwrap();
+        if properties.preset != preset {
+            properties.preset = preset.to_string();
+            drop(properties);
+            self.emit_changed(|visualizer, emitter| {
+                zbus::block_on(visualizer.current_preset_changed(emitter))
+            });
+        }
+    }
+
+    pub fn set_fps(&self, fps: f64) {
+        let mut properties = self.properties.lock().unwrap();
+        properties.fps = fps;
+        properties.fps_updated = Instant::now();
+    }
+
+    pub fn beat(&self, bpm: Option<f32>) {
+        let result = SignalEmitter::new(self.connection.inner(), OBJECT_PATH).and_then(|emitter| {
+            zbus::block_on(Visualizer::beat(&emitter, bpm.unwrap_or(0.0) as f64))
+        });
+        if let Err(error) = result {
+            eprintln!("Failed to emit Beat: {}", error);
+        }
+    }
+
+    fn emit_changed(
+        &self,
+        emit: impl FnOnce(&Visualizer, &SignalEmitter<'static>) -> zbus::Result<()>,
+    ) {
+        // A read lock, method calls keep theirs until the event loop answered them.
+        let result = self
+            .connection
+            .object_server()
+            .interface::<_, Visualizer>(OBJECT_PATH)
+            .and_then(|interface| emit(&interface.get(), interface.signal_emitter()));
+        if let Err(error) = result {
+            eprintln!("Failed to emit PropertiesChanged: {}", error);
+        }
+    }
+}
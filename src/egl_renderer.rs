@@ -0,0 +1,197 @@
+use crate::app_config::Config;
+use crate::appearance::Appearance;
+use crate::background::Background;
+use crate::beat::EffectLevels;
+use crate::gl_context::{create_context, GlApi};
+use crate::renderer::{GlRenderer, Renderer};
+use core::ffi;
+use egl::API as egl;
+use std::error::Error;
+use std::ffi::CStr;
+use std::mem::ManuallyDrop;
+use wayland_client::protocol::wl_surface::WlSurface;
+use wayland_client::{Connection, Proxy};
+use wayland_egl::WlEglSurface;
+
+/// Draws with `GlRenderer` into an EGL window surface on the layer surface.
+pub struct EglRenderer {
+    wl_egl_surface: WlEglSurface,
+    egl_surface: egl::Surface,
+    egl_config: egl::Config,
+    egl_context: egl::Context,
+    egl_display: egl::Display,
+    // Dropped by hand while the context is still current.
+    renderer: ManuallyDrop<GlRenderer>,
+    api: GlApi,
+    width: u32,
+    height: u32,
+}
+
+impl EglRenderer {
+    pub fn new(
+        conn: &Connection,
+        surface: &WlSurface,
+        config: &Config,
+    ) -> Result<Self, Box<dyn Error>> {
+        let egl_display = unsafe {
+            egl.get_display(conn.display().id().as_ptr() as *mut std::ffi::c_void)
+                .ok_or("No EGL display for the Wayland connection")?
+        };
+        egl.initialize(egl_display)?;
+        let mut partial = PartialEgl {
+            display: egl_display,
+            context: None,
+            surface: None,
+            wl_egl_surface: None,
+        };
+        let gl_context = create_context(egl_display, egl::WINDOW_BIT)?;
+        partial.context = Some(gl_context.context);
+
+        let wl_egl_surface =
+            partial
+                .wl_egl_surface
+                .insert(WlEglSurface::new(surface.id(), 256, 256)?);
+        let egl_surface = unsafe {
+            egl.create_window_surface(
+                egl_display,
+                gl_context.config,
+                wl_egl_surface.ptr() as egl::NativeWindowType,
+                None,
+            )?
+        };
+        partial.surface = Some(egl_surface);
+        egl.make_current(
+            egl_display,
+            Some(egl_surface),
+            Some(egl_surface),
+            Some(gl_context.context),
+        )?;
+        gl::load_with(|name| egl.get_proc_address(name).unwrap() as *const std::ffi::c_void);
+        let version = unsafe {
+            let data = gl::GetString(gl::VERSION) as *const ffi::c_char;
+            CStr::from_ptr(data).to_string_lossy().into_owned()
+        };
+
+        println!("OpenGL version: {}", version);
+        println!("EGL version: {}", egl.version());
+        println!("Context API: {}", gl_context.api);
+        let renderer = GlRenderer::new(gl_context.api, config)?;
+        let wl_egl_surface = partial.wl_egl_surface.take().unwrap();
+        // EglRenderer owns all of it from here on.
+        std::mem::forget(partial);
+        Ok(EglRenderer {
+            wl_egl_surface,
+            egl_surface,
+            egl_config: gl_context.config,
+            egl_context: gl_context.context,
+            egl_display,
+            renderer: ManuallyDrop::new(renderer),
+            api: gl_context.api,
+            width: 256,
+            height: 256,
+        })
+    }
+}
+
+impl Renderer for EglRenderer {
+    fn configure(&mut self, surface: &WlSurface, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        egl.destroy_surface(self.egl_display, self.egl_surface)
+            .unwrap();
+        self.wl_egl_surface =
+            WlEglSurface::new(surface.id(), self.width as i32, self.height as i32).unwrap();
+        self.egl_surface = unsafe {
+            egl.create_window_surface(
+                self.egl_display,
+                self.egl_config,
+                self.wl_egl_surface.ptr() as egl::NativeWindowType,
+                None,
+            )
+            .unwrap()
+        };
+        egl.make_current(
+            self.egl_display,
+            Some(self.egl_surface),
+            Some(self.egl_surface),
+            Some(self.egl_context),
+        )
+        .unwrap();
+    }
+
+    fn set_effects(&mut self, effects: EffectLevels) {
+        self.renderer.set_effects(effects);
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.renderer.set_opacity(opacity);
+    }
+
+    fn reload(&mut self, config: &Config) {
+        match GlRenderer::new(self.api, config) {
+            Ok(mut renderer) => {
+                renderer.set_background(self.renderer.take_background());
+                let old_renderer =
+                    std::mem::replace(&mut self.renderer, ManuallyDrop::new(renderer));
+                drop(ManuallyDrop::into_inner(old_renderer));
+            }
+            Err(error) => eprintln!("Failed to reload the renderer: {}", error),
+        }
+    }
+
+    fn set_appearance(&mut self, appearance: &Appearance) {
+        self.renderer.set_appearance(appearance);
+    }
+
+    fn set_background(&mut self, background: Option<Background>) {
+        self.renderer.set_background(background);
+    }
+
+    fn fade_background(&mut self, background: Option<Background>) {
+        self.renderer.fade_background(background);
+    }
+
+    fn set_background_fade(&mut self, progress: f32) {
+        self.renderer.set_background_fade(progress);
+    }
+
+    fn draw(&mut self, _surface: &WlSurface, bar_values: &[f32]) {
+        self.renderer.draw(bar_values, self.width, self.height);
+        egl.swap_buffers(self.egl_display, self.egl_surface)
+            .unwrap();
+    }
+}
+
+impl Drop for EglRenderer {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.renderer) };
+        let _ = egl.make_current(self.egl_display, None, None, None);
+        let _ = egl.destroy_surface(self.egl_display, self.egl_surface);
+        let _ = egl.destroy_context(self.egl_display, self.egl_context);
+        let _ = egl.terminate(self.egl_display);
+    }
+}
+
+// What EglRenderer::new set up so far, torn down when it fails part way, e.g.
+// before falling back to the software renderer.
+struct PartialEgl {
+    display: egl::Display,
+    context: Option<egl::Context>,
+    surface: Option<egl::Surface>,
+    wl_egl_surface: Option<WlEglSurface>,
+}
+
+impl Drop for PartialEgl {
+    fn drop(&mut self) {
+        let _ = egl.make_current(self.display, None, None, None);
+        if let Some(surface) = self.surface {
+            let _ = egl.destroy_surface(self.display, surface);
+        }
+        // Only after the EGL surface made from it is gone.
+        self.wl_egl_surface = None;
+        if let Some(context) = self.context {
+            let _ = egl.destroy_context(self.display, context);
+        }
+        let _ = egl.terminate(self.display);
+    }
+}
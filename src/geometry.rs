@@ -0,0 +1,136 @@
+/// Horizontal layout of the bars in normalized device coordinates, the whole
+/// width from -1.0 to 1.0 is covered by `bar_count` bars and the gaps between them.
+#[derive(Debug, Clone, Copy)]
+pub struct BarLayout {
+    pub bar_count: u32,
+    /// Gap width as a fraction of the bar width.
+    pub bar_gap: f32,
+}
+
+impl BarLayout {
+    pub fn new(bar_count: u32, bar_gap: f32) -> Self {
+        BarLayout { bar_count, bar_gap }
+    }
+
+    pub fn bar_width(&self) -> f32 {
+        2.0 / (self.bar_count as f32 + (self.bar_count as f32 - 1.0) * self.bar_gap)
+    }
+
+    /// Left and right edge of bar `index`.
+    pub fn bar_edges(&self, index: usize) -> (f32, f32) {
+        let bar_width = self.bar_width();
+        let bar_gap_width = bar_width * self.bar_gap;
+        let left = bar_gap_width * index as f32 + bar_width * index as f32 - 1.0;
+        (left, left + bar_width)
+    }
+
+    /// Top edge of a bar with a normalized value.
+    pub fn bar_top(value: f32) -> f32 {
+        2.0 * value - 1.0
+    }
+
+    /// Four `vec2` corners per bar (top left, top right, bottom left, bottom right),
+    /// matching the order expected by `indices`.
+    pub fn vertices(&self, bar_values: &[f32], vertices: &mut Vec<f32>) {
+        vertices.clear();
+        for (i, bar_value) in bar_values.iter().enumerate().take(self.bar_count as usize) {
+            let (left, right) = self.bar_edges(i);
+            let top = Self::bar_top(*bar_value);
+            vertices.extend_from_slice(&[left, top, right, top, left, -1.0, right, -1.0]);
+        }
+    }
+
+    /// Two triangles per bar.
+    pub fn indices(&self) -> Vec<u16> {
+        let mut indices: Vec<u16> = vec![0; self.bar_count as usize * 6];
+        for i in 0..self.bar_count as usize {
+            indices[i * 6] = i as u16 * 4;
+            indices[i * 6 + 1] = i as u16 * 4 + 1;
+            indices[i * 6 + 2] = i as u16 * 4 + 2;
+            indices[i * 6 + 3] = i as u16 * 4 + 1;
+            indices[i * 6 + 4] = i as u16 * 4 + 2;
+            indices[i * 6 + 5] = i as u16 * 4 + 3;
+        }
+        indices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-6;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < EPSILON,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn bars_and_gaps_cover_the_width() {
+        for (bar_count, bar_gap) in [(1, 0.5), (3, 0.0), (7, 0.2), (76, 0.1), (200, 1.5)] {
+            let layout = BarLayout::new(bar_count, bar_gap);
+            let bar_width = layout.bar_width();
+            let (first_left, _) = layout.bar_edges(0);
+            let (_, last_right) = layout.bar_edges(bar_count as usize - 1);
+            assert_close(first_left, -1.0);
+            assert_close(last_right, 1.0);
+            for index in 0..bar_count as usize {
+                let (left, right) = layout.bar_edges(index);
+                assert_close(right - left, bar_width);
+                if index > 0 {
+                    let (_, previous_right) = layout.bar_edges(index - 1);
+                    assert_close(left - previous_right, bar_width * bar_gap);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lays_out_bars_in_pixels() {
+        // Three bars with half-width gaps on 16 pixels: 4, 2, 4, 2, 4.
+        let layout = BarLayout::new(3, 0.5);
+        let to_pixels = |ndc: f32| (ndc + 1.0) / 2.0 * 16.0;
+        let edges: Vec<(f32, f32)> = (0..3)
+            .map(|index| {
+                let (left, right) = layout.bar_edges(index);
+                (to_pixels(left), to_pixels(right))
+            })
+            .collect();
+        for ((left, right), (expected_left, expected_right)) in
+            edges
+                .into_iter()
+                .zip([(0.0, 4.0), (6.0, 10.0), (12.0, 16.0)])
+        {
+            assert_close(left, expected_left);
+            assert_close(right, expected_right);
+        }
+        // 7 bars don't divide 100 pixels evenly, each one is 100 / 7 wide.
+        let layout = BarLayout::new(7, 0.0);
+        let (left, right) = layout.bar_edges(3);
+        assert_close((right - left) / 2.0 * 100.0, 100.0 / 7.0);
+        assert_close(left, -1.0 + 3.0 * 2.0 / 7.0);
+    }
+
+    #[test]
+    fn builds_a_quad_per_bar() {
+        let layout = BarLayout::new(2, 0.0);
+        let mut vertices = vec![9.0; 3];
+        // Values past bar_count are ignored.
+        layout.vertices(&[1.0, 0.25, 0.5], &mut vertices);
+        assert_eq!(
+            vertices,
+            [
+                -1.0, 1.0, 0.0, 1.0, -1.0, -1.0, 0.0, -1.0, 0.0, -0.5, 1.0, -0.5, 0.0, -1.0, 1.0,
+                -1.0
+            ]
+        );
+        layout.vertices(&[0.0], &mut vertices);
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(layout.indices(), [0, 1, 2, 1, 2, 3, 4, 5, 6, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,158 @@
+use egl::API as egl;
+use std::fmt;
+
+/// Context flavours we know how to render with, in the order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlApi {
+    Gl46,
+    Gl33,
+    Gles30,
+}
+
+impl GlApi {
+    pub const PREFERENCE: [GlApi; 3] = [GlApi::Gl46, GlApi::Gl33, GlApi::Gles30];
+
+    /// Only GL 4.3+ has shader storage buffers, older contexts get the
+    /// gradient colors through a uniform array instead.
+    pub fn supports_ssbo(self) -> bool {
+        self == GlApi::Gl46
+    }
+
+    /// `#version` line (plus default precision for GLES) for the fallback shaders.
+    pub fn fallback_shader_header(self) -> &'static str {
+        match self {
+            GlApi::Gl46 | GlApi::Gl33 => "#version 330 core\n",
+            GlApi::Gles30 => "#version 300 es\nprecision highp float;\nprecision highp int;\n",
+        }
+    }
+
+    fn egl_api(self) -> egl::Enum {
+        match self {
+            GlApi::Gl46 | GlApi::Gl33 => egl::OPENGL_API,
+            GlApi::Gles30 => egl::OPENGL_ES_API,
+        }
+    }
+
+    fn renderable_type(self) -> egl::Int {
+        match self {
+            GlApi::Gl46 | GlApi::Gl33 => egl::OPENGL_BIT,
+            GlApi::Gles30 => egl::OPENGL_ES3_BIT,
+        }
+    }
+
+    fn context_attributes(self) -> &'static [egl::Int] {
+        match self {
+            GlApi::Gl46 => &[
+                egl::CONTEXT_MAJOR_VERSION,
+                4,
+                egl::CONTEXT_MINOR_VERSION,
+                6,
+                egl::CONTEXT_OPENGL_PROFILE_MASK,
+                egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                egl::NONE,
+            ],
+            GlApi::Gl33 => &[
+                egl::CONTEXT_MAJOR_VERSION,
+                3,
+                egl::CONTEXT_MINOR_VERSION,
+                3,
+                egl::CONTEXT_OPENGL_PROFILE_MASK,
+                egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                egl::NONE,
+            ],
+            GlApi::Gles30 => &[
+                egl::CONTEXT_MAJOR_VERSION,
+                3,
+                egl::CONTEXT_MINOR_VERSION,
+                0,
+                egl::NONE,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for GlApi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GlApi::Gl46 => write!(f, "OpenGL 4.6 core"),
+            GlApi::Gl33 => write!(f, "OpenGL 3.3 core"),
+            GlApi::Gles30 => write!(f, "OpenGL ES 3.0"),
+        }
+    }
+}
+
+pub struct GlContext {
+    pub api: GlApi,
+    pub config: egl::Config,
+    pub context: egl::Context,
+}
+
+#[derive(Debug)]
+pub struct ContextError {
+    pub attempts: Vec<(GlApi, String)>,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to create any OpenGL context:")?;
+        for (api, reason) in self.attempts.iter() {
+            write!(f, "\n  {}: {}", api, reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ContextError {}
+
+/// Tries every API in `GlApi::PREFERENCE` and returns the first context the
+/// driver accepts. `surface_type` is the EGL surface bit the config must support.
+/// The matching client API is left bound on success.
+pub fn create_context(
+    display: egl::Display,
+    surface_type: egl::Int,
+) -> Result<GlContext, ContextError> {
+    let mut attempts = Vec::new();
+    for api in GlApi::PREFERENCE {
+        match try_create_context(display, surface_type, api) {
+            Ok(context) => return Ok(context),
+            Err(reason) => attempts.push((api, reason)),
+        }
+    }
+    Err(ContextError { attempts })
+}
+
+fn try_create_context(
+    display: egl::Display,
+    surface_type: egl::Int,
+    api: GlApi,
+) -> Result<GlContext, String> {
+    egl.bind_api(api.egl_api())
+        .map_err(|error| error.to_string())?;
+    let attributes = [
+        egl::RED_SIZE,
+        8,
+        egl::GREEN_SIZE,
+        8,
+        egl::BLUE_SIZE,
+        8,
+        egl::ALPHA_SIZE,
+        8,
+        egl::SURFACE_TYPE,
+        surface_type,
+        egl::RENDERABLE_TYPE,
+        api.renderable_type(),
+        egl::NONE,
+    ];
+    let config = egl
+        .choose_first_config(display, &attributes)
+        .map_err(|error| error.to_string())?
+        .ok_or_else(|| "no matching EGL config".to_string())?;
+    let context = egl
+        .create_context(display, config, None, api.context_attributes())
+        .map_err(|error| error.to_string())?;
+    Ok(GlContext {
+        api,
+        config,
+        context,
+    })
+}
@@ -0,0 +1,289 @@
+use crate::app_config::Config;
+use crate::audio::decoder::{FrameDecoder, FrameFormat};
+use crate::audio::recording::Recording;
+use crate::background::Background;
+use crate::gl_context::create_context;
+use crate::renderer::GlRenderer;
+use egl::API as egl;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+pub struct RenderOptions {
+    pub config_filename: String,
+    pub preset: Option<String>,
+    pub frames_filename: String,
+    pub width: u32,
+    pub height: u32,
+    pub out_filename: String,
+    pub frame_index: Option<usize>,
+}
+
+impl RenderOptions {
+    /// Parses the arguments following `render`.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut config_filename = "config.toml".to_string();
+        let mut preset = None;
+        let mut frames_filename = None;
+        let mut size = None;
+        let mut out_filename = None;
+        let mut frame_index = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--config" => config_filename = value.clone(),
+                "--preset" => preset = Some(value.clone()),
+                "--frames" => frames_filename = Some(value.clone()),
+                "--size" => size = Some(parse_size(value)?),
+                "--out" => out_filename = Some(value.clone()),
+                "--frame" => {
+                    frame_index = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid frame index: {}", value))?,
+                    )
+                }
+                _ => return Err(format!("Unknown render option: {}", arg)),
+            }
+        }
+        let (width, height) = size.unwrap_or((1920, 1080));
+        Ok(RenderOptions {
+            config_filename,
+            preset,
+            frames_filename: frames_filename.ok_or("--frames is required")?,
+            width,
+            height,
+            out_filename: out_filename.ok_or("--out is required")?,
+            frame_index,
+        })
+    }
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid size {}, expected WIDTHxHEIGHT", size);
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+/// Renders one recorded frame offscreen and writes it as a PNG. The frames
+/// file is either a recording made with `--record` or holds cava's raw 16bit
+/// output, `bars.amount` values per frame. Without `--frame` the last complete
+/// frame is used.
+pub fn render_to_png(config: &Config, options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+    let bar_count = config.bars.amount as usize;
+    let frames = load_frames(&options.frames_filename, bar_count)?;
+    if frames.is_empty() {
+        return Err(format!("{} holds no complete frame", options.frames_filename).into());
+    }
+    let frame_index = options.frame_index.unwrap_or(frames.len() - 1);
+    let Some(bar_values) = frames.get(frame_index) else {
+        return Err(format!(
+            "Frame {} requested, but {} only holds {} frames",
+            frame_index,
+            options.frames_filename,
+            frames.len()
+        )
+        .into());
+    };
+
+    let background = Background::load(config)?;
+
+    // Surfaceless works without any windowing system, e.g. Mesa llvmpipe in CI.
+    let egl_display = unsafe {
+        match egl.get_platform_display(
+            PLATFORM_SURFACELESS_MESA,
+            egl::DEFAULT_DISPLAY,
+            &[egl::ATTRIB_NONE],
+        ) {
+            Ok(display) => display,
+            Err(_) => egl
+                .get_display(egl::DEFAULT_DISPLAY)
+                .ok_or("No EGL display available")?,
+        }
+    };
+    egl.initialize(egl_display)?;
+    let gl_context = create_context(egl_display, egl::PBUFFER_BIT)?;
+    let egl_surface = egl.create_pbuffer_surface(
+        egl_display,
+        gl_context.config,
+        &[
+            egl::WIDTH,
+            options.width as egl::Int,
+            egl::HEIGHT,
+            options.height as egl::Int,
+            egl::NONE,
+        ],
+    )?;
+    egl.make_current(
+        egl_display,
+        Some(egl_surface),
+        Some(egl_surface),
+        Some(gl_context.context),
+    )?;
+    gl::load_with(|name| egl.get_proc_address(name).unwrap() as *const std::ffi::c_void);
+
+    let mut renderer = GlRenderer::new(gl_context.api, config)?;
+    renderer.set_background(background);
+    renderer.draw(bar_values, options.width, options.height);
+    let pixels = read_pixels(options.width, options.height);
+    drop(renderer);
+
+    egl.make_current(egl_display, None, None, None)?;
+    egl.destroy_surface(egl_display, egl_surface)?;
+    egl.destroy_context(egl_display, gl_context.context)?;
+    egl.terminate(egl_display)?;
+
+    let file = File::create(&options.out_filename)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), options.width, options.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    Ok(())
+}
+
+fn load_frames(filename: &str, bar_count: usize) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    let data = fs::read(filename)?;
+    if Recording::is_recording(&data) {
+        let recording = Recording::parse(&data)?;
+        if recording.header.bar_count as usize != bar_count {
+            return Err(format!(
+                "{} has {} bars, but bars.amount is {}",
+                filename, recording.header.bar_count, bar_count
+            )
+            .into());
+        }
+        return Ok(recording
+            .frames
+            .into_iter()
+            .map(|frame| frame.bar_values)
+            .collect());
+    }
+    let mut decoder = FrameDecoder::new(FrameFormat::Bit16, bar_count);
+    decoder.feed(&data);
+    let mut frames = Vec::new();
+    let mut bar_values = vec![0.0; bar_count];
+    while decoder.next_frame(&mut bar_values)? {
+        frames.push(bar_values.clone());
+    }
+    Ok(frames)
+}
+
+// Returns RGBA rows top to bottom, GL hands them out bottom to top.
+fn read_pixels(width: u32, height: u32) -> Vec<u8> {
+    let row_size = width as usize * 4;
+    let mut pixels = vec![0u8; row_size * height as usize];
+    unsafe {
+        gl::Finish();
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _,
+        );
+    }
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks_exact(row_size).rev() {
+        flipped.extend_from_slice(row);
+    }
+    flipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1920x1080"), Ok((1920, 1080)));
+        assert_eq!(parse_size("1x1"), Ok((1, 1)));
+        for size in [
+            "0x0", "0x1080", "1920x0", "1920x", "x1080", "1920", "-1x1", "axb", "",
+        ] {
+            assert!(parse_size(size).is_err(), "{} was accepted", size);
+        }
+    }
+
+    #[test]
+    fn parses_options_with_defaults() {
+        let options =
+            RenderOptions::from_args(&args(&["--frames", "frames.raw", "--out", "out.png"]))
+                .unwrap();
+        assert_eq!(options.config_filename, "config.toml");
+        assert_eq!(options.preset, None);
+        assert_eq!((options.width, options.height), (1920, 1080));
+        assert_eq!(options.frame_index, None);
+
+        let options = RenderOptions::from_args(&args(&[
+            "--out",
+            "out.png",
+            "--size",
+            "64x48",
+            "--frame",
+            "3",
+            "--config",
+            "test.toml",
+            "--preset",
+            "night",
+            "--frames",
+            "frames.raw",
+        ]))
+        .unwrap();
+        assert_eq!(options.frames_filename, "frames.raw");
+        assert_eq!(options.out_filename, "out.png");
+        assert_eq!(options.config_filename, "test.toml");
+        assert_eq!(options.preset.as_deref(), Some("night"));
+        assert_eq!((options.width, options.height), (64, 48));
+        assert_eq!(options.frame_index, Some(3));
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        for bad in [
+            &["--out", "out.png"][..],
+            &["--frames", "frames.raw"],
+            &["--frames", "frames.raw", "--out"],
+            &[
+                "--frames",
+                "frames.raw",
+                "--out",
+                "out.png",
+                "--size",
+                "1920x",
+            ],
+            &[
+                "--frames",
+                "frames.raw",
+                "--out",
+                "out.png",
+                "--frame",
+                "-1",
+            ],
+            &["--frames", "frames.raw", "--out", "out.png", "--scale", "2"],
+        ] {
+            assert!(
+                RenderOptions::from_args(&args(bad)).is_err(),
+                "{:?} was accepted",
+                bad
+            );
+        }
+    }
+}
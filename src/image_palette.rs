@@ -0,0 +1,238 @@
+use image::{imageops, RgbaImage};
+use std::path::Path;
+
+// Colors found in the image, the darkest becomes the background.
+const PALETTE_SIZE: usize = 7;
+const KMEANS_ITERATIONS: usize = 16;
+// Images are scaled down to at most this many pixels on each side first.
+const SAMPLE_SIZE: u32 = 128;
+// Colors covering less of the image are noise.
+const MIN_SHARE: f32 = 0.005;
+// Oklab lightness difference between the bars and the image from which no
+// background is needed, the background becomes more opaque below it.
+const MIN_CONTRAST: f32 = 0.3;
+const MAX_BACKGROUND_ALPHA: f32 = 0.7;
+
+/// Colors derived from a wallpaper for `colors.import.from_image`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImagePalette {
+    /// `#RRGGBB`, darkest to lightest.
+    pub gradient: Vec<String>,
+    pub background: String,
+    pub background_alpha: f32,
+}
+
+impl ImagePalette {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|error| format!("Unable to read {}: {}", path.display(), error))?;
+        ImagePalette::from_image(&image.to_rgba8())
+            .ok_or_else(|| format!("{} is empty", path.display()))
+    }
+
+    /// Like `load`, for an image that is already decoded. None if it is empty.
+    pub fn from_image(image: &RgbaImage) -> Option<Self> {
+        let scale = (SAMPLE_SIZE as f32 / image.width().max(image.height()) as f32).min(1.0);
+        let width = ((image.width() as f32 * scale).round() as u32).max(1);
+        let height = ((image.height() as f32 * scale).round() as u32).max(1);
+        let thumbnail;
+        let image = if scale < 1.0 {
+            thumbnail = imageops::thumbnail(image, width, height);
+            &thumbnail
+        } else {
+            image
+        };
+        let pixels: Vec<[u8; 3]> = image
+            .pixels()
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        ImagePalette::from_pixels(&pixels)
+    }
+
+    /// Clusters the pixels with k-means in Oklab, where distances follow perceived
+    /// differences. The darkest cluster is the background, the others form the gradient.
+    pub fn from_pixels(pixels: &[[u8; 3]]) -> Option<Self> {
+        if pixels.is_empty() {
+            return None;
+        }
+        let points: Vec<[f32; 3]> = pixels.iter().map(|pixel| oklab_from_srgb(*pixel)).collect();
+        let mut clusters = kmeans(&points, PALETTE_SIZE);
+        clusters.retain(|(_, count)| *count as f32 >= points.len() as f32 * MIN_SHARE);
+        clusters.sort_by(|(a, _), (b, _)| a[0].total_cmp(&b[0]));
+
+        let image_lightness =
+            points.iter().map(|point| point[0]).sum::<f32>() / points.len() as f32;
+        let (background, _) = clusters[0];
+        let mut gradient: Vec<[f32; 3]> = clusters[1..].iter().map(|(color, _)| *color).collect();
+        if gradient.is_empty() {
+            gradient.push(background);
+        }
+        // The shaders need at least two colors to interpolate between.
+        if gradient.len() == 1 {
+            gradient.push(gradient[0]);
+        }
+        let bar_lightness =
+            gradient.iter().map(|color| color[0]).sum::<f32>() / gradient.len() as f32;
+        let contrast = (bar_lightness - image_lightness).abs();
+        Some(ImagePalette {
+            gradient: gradient
+                .iter()
+                .map(|color| hex_from_oklab(*color))
+                .collect(),
+            background: hex_from_oklab(background),
+            background_alpha: ((MIN_CONTRAST - contrast) / MIN_CONTRAST)
+                .clamp(0.0, MAX_BACKGROUND_ALPHA),
+        })
+    }
+}
+
+// Centroids and how many points ended up closest to each.
+fn kmeans(points: &[[f32; 3]], k: usize) -> Vec<([f32; 3], usize)> {
+    // Farthest point initialization, deterministic so the same wallpaper always
+    // gives the same colors.
+    let mut centroids = vec![points[0]];
+    let mut distances: Vec<f32> = points
+        .iter()
+        .map(|point| distance(point, &points[0]))
+        .collect();
+    while centroids.len() < k {
+        let (index, farthest) = distances
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        if *farthest == 0.0 {
+            break;
+        }
+        let centroid = points[index];
+        centroids.push(centroid);
+        for (distance_to_nearest, point) in distances.iter_mut().zip(points) {
+            *distance_to_nearest = distance_to_nearest.min(distance(point, &centroid));
+        }
+    }
+
+    let mut counts = vec![0; centroids.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![[0.0f32; 3]; centroids.len()];
+        counts.fill(0);
+        for point in points {
+            let nearest = nearest(&centroids, point);
+            for channel in 0..3 {
+                sums[nearest][channel] += point[channel];
+            }
+            counts[nearest] += 1;
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(&sums).zip(&counts) {
+            if *count > 0 {
+                *centroid = sum.map(|channel| channel / *count as f32);
+            }
+        }
+    }
+    centroids.into_iter().zip(counts).collect()
+}
+
+fn nearest(centroids: &[[f32; 3]], point: &[f32; 3]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a, point).total_cmp(&distance(b, point)))
+        .map(|(index, _)| index)
+        .unwrap()
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+// https://bottosson.github.io/posts/oklab/, in f64 for the precision of the constants.
+fn oklab_from_srgb(pixel: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = pixel.map(|channel| {
+        let channel = channel as f64 / 255.0;
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+    .map(|channel| channel as f32)
+}
+
+fn hex_from_oklab(color: [f32; 3]) -> String {
+    let [lightness, a, b] = color.map(f64::from);
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    let [r, g, b] = [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+    .map(|channel| {
+        let channel = if channel <= 0.0031308 {
+            channel * 12.92
+        } else {
+            1.055 * channel.powf(1.0 / 2.4) - 0.055
+        };
+        (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+    });
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn round_trips_through_oklab() {
+        for pixel in [[0, 0, 0], [255, 255, 255], [148, 226, 213], [243, 139, 168]] {
+            let hex = format!("#{:02x}{:02x}{:02x}", pixel[0], pixel[1], pixel[2]);
+            assert_eq!(hex_from_oklab(oklab_from_srgb(pixel)), hex);
+        }
+    }
+
+    #[test]
+    fn darkest_color_becomes_the_background() {
+        let mut pixels = vec![[20, 20, 30]; 600];
+        pixels.extend(vec![[240, 140, 170]; 200]);
+        pixels.extend(vec![[150, 225, 215]; 200]);
+        // Too rare to make it into the palette.
+        pixels.push([255, 255, 0]);
+        let palette = ImagePalette::from_pixels(&pixels).unwrap();
+        assert_eq!(palette.background, "#14141e");
+        assert_eq!(palette.gradient, vec!["#f08caa", "#96e1d7"]);
+        assert!(palette.background_alpha < MAX_BACKGROUND_ALPHA);
+    }
+
+    #[test]
+    fn low_contrast_images_get_an_opaque_background() {
+        let mut pixels = vec![[200, 200, 200]; 500];
+        pixels.extend(vec![[210, 200, 190]; 500]);
+        let palette = ImagePalette::from_pixels(&pixels).unwrap();
+        assert_eq!(palette.gradient.len(), 2);
+        assert!(palette.background_alpha > 0.5);
+    }
+
+    #[test]
+    fn samples_decoded_images() {
+        let image = RgbaImage::from_fn(512, 256, |x, _| {
+            if x < 384 {
+                Rgba([20, 20, 30, 255])
+            } else {
+                Rgba([240, 140, 170, 255])
+            }
+        });
+        let palette = ImagePalette::from_image(&image).unwrap();
+        assert_eq!(palette.background, "#14141e");
+        assert_eq!(palette.gradient, vec!["#f08caa", "#f08caa"]);
+        assert_eq!(ImagePalette::from_image(&RgbaImage::new(0, 0)), None);
+    }
+}
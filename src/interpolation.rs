@@ -0,0 +1,194 @@
+use crate::app_config::Interpolation;
+use std::time::{Duration, Instant};
+
+// A spring is settled once it is this close to its target and this slow.
+const SETTLED_DISTANCE: f32 = 0.001;
+
+/// Smooths the steps between audio frames so the bars can be drawn at the
+/// display's refresh rate. Linear interpolation moves from the bars shown when
+/// a frame arrived to that frame within one audio frame. The spring follows the
+/// latest frame critically damped, reaching it within about one audio frame.
+pub struct Interpolator {
+    mode: Interpolation,
+    frame_duration: Duration,
+    // Linear: the bars shown when the target arrived.
+    start: Vec<f32>,
+    target: Vec<f32>,
+    current: Vec<f32>,
+    velocity: Vec<f32>,
+    target_time: Instant,
+    last_sample: Instant,
+    animating: bool,
+}
+
+impl Interpolator {
+    /// `framerate` is the rate audio frames arrive at.
+    pub fn new(mode: Interpolation, bar_count: usize, framerate: u32) -> Self {
+        let now = Instant::now();
+        Interpolator {
+            mode,
+            frame_duration: Duration::from_secs(1) / framerate.max(1),
+            start: vec![0.0; bar_count],
+            target: vec![0.0; bar_count],
+            current: vec![0.0; bar_count],
+            velocity: vec![0.0; bar_count],
+            target_time: now,
+            last_sample: now,
+            animating: false,
+        }
+    }
+
+    pub fn push(&mut self, bar_values: &[f32], now: Instant) {
+        self.start.copy_from_slice(&self.current);
+        self.target.copy_from_slice(bar_values);
+        self.target_time = now;
+        if !self.animating {
+            // The spring was at rest, don't integrate over the idle time.
+            self.last_sample = now;
+        }
+        self.animating = true;
+    }
+
+    /// Writes the bars to show at `now` into `bar_values`.
+    pub fn sample(&mut self, now: Instant, bar_values: &mut [f32]) {
+        match self.mode {
+            Interpolation::None => {
+                self.current.copy_from_slice(&self.target);
+                self.animating = false;
+            }
+            Interpolation::Linear => {
+                let progress = (now
+                    .saturating_duration_since(self.target_time)
+                    .as_secs_f32()
+                    / self.frame_duration.as_secs_f32())
+                .min(1.0);
+                for ((current, start), target) in
+                    self.current.iter_mut().zip(&self.start).zip(&self.target)
+                {
+                    *current = start + (target - start) * progress;
+                }
+                self.animating = progress < 1.0;
+            }
+            Interpolation::Spring => {
+                let dt = now
+                    .saturating_duration_since(self.last_sample)
+                    .as_secs_f32();
+                let omega = std::f32::consts::TAU / self.frame_duration.as_secs_f32();
+                let decay = (-omega * dt).exp();
+                let mut settled = true;
+                for ((current, velocity), target) in self
+                    .current
+                    .iter_mut()
+                    .zip(self.velocity.iter_mut())
+                    .zip(&self.target)
+                {
+                    let distance = *current - target;
+                    let change = (*velocity + omega * distance) * dt;
+                    *current = target + (distance + change) * decay;
+                    *velocity = (*velocity - omega * change) * decay;
+                    if (*current - target).abs() > SETTLED_DISTANCE
+                        || (*velocity * self.frame_duration.as_secs_f32()).abs() > SETTLED_DISTANCE
+                    {
+                        settled = false;
+                    }
+                }
+                if settled {
+                    self.current.copy_from_slice(&self.target);
+                    self.velocity.fill(0.0);
+                }
+                self.animating = !settled;
+            }
+        }
+        self.last_sample = now;
+        for (value, current) in bar_values.iter_mut().zip(&self.current) {
+            *value = current.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Whether sampling again would show different bars.
+    pub fn is_animating(&self) -> bool {
+        self.animating
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMERATE: u32 = 50;
+    const FRAME: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn none_shows_the_latest_frame() {
+        let now = Instant::now();
+        let mut interpolator = Interpolator::new(Interpolation::None, 1, FRAMERATE);
+        let mut bar_values = [0.0];
+        interpolator.push(&[0.8], now);
+        assert!(interpolator.is_animating());
+        interpolator.sample(now, &mut bar_values);
+        assert_eq!(bar_values, [0.8]);
+        assert!(!interpolator.is_animating());
+    }
+
+    #[test]
+    fn linear_reaches_the_frame_within_one_frame() {
+        let now = Instant::now();
+        let mut interpolator = Interpolator::new(Interpolation::Linear, 2, FRAMERATE);
+        let mut bar_values = [0.0; 2];
+        interpolator.push(&[0.8, 0.2], now);
+        interpolator.sample(now + FRAME / 2, &mut bar_values);
+        assert!((bar_values[0] - 0.4).abs() < 1e-5, "{:?}", bar_values);
+        assert!((bar_values[1] - 0.1).abs() < 1e-5, "{:?}", bar_values);
+        assert!(interpolator.is_animating());
+        for step in [FRAME, FRAME * 2, FRAME * 10] {
+            interpolator.sample(now + step, &mut bar_values);
+            assert_eq!(bar_values, [0.8, 0.2]);
+        }
+        assert!(!interpolator.is_animating());
+
+        // Starts from what is shown when the next frame arrives early.
+        let next = now + FRAME * 10;
+        interpolator.push(&[0.0, 0.2], next);
+        interpolator.sample(next + FRAME / 4, &mut bar_values);
+        interpolator.push(&[0.4, 0.2], next + FRAME / 4);
+        interpolator.sample(next + FRAME / 4, &mut bar_values);
+        assert!((bar_values[0] - 0.6).abs() < 1e-5, "{:?}", bar_values);
+        let mut previous = bar_values[0];
+        for step in 1..=10 {
+            interpolator.sample(next + FRAME / 4 + FRAME * step / 10, &mut bar_values);
+            assert!(bar_values[0] <= previous && bar_values[0] >= 0.4);
+            previous = bar_values[0];
+        }
+        assert_eq!(bar_values[0], 0.4);
+    }
+
+    #[test]
+    fn spring_settles_without_overshooting() {
+        let start = Instant::now();
+        let mut interpolator = Interpolator::new(Interpolation::Spring, 1, FRAMERATE);
+        let mut bar_values = [0.0];
+        interpolator.push(&[0.8], start);
+        let mut previous = 0.0;
+        let mut settled_at = None;
+        for millis in 1..=200 {
+            interpolator.sample(start + Duration::from_millis(millis), &mut bar_values);
+            assert!(bar_values[0] >= previous, "fell back at {}ms", millis);
+            assert!(bar_values[0] <= 0.8, "overshot at {}ms", millis);
+            previous = bar_values[0];
+            if !interpolator.is_animating() {
+                settled_at = Some(millis);
+                break;
+            }
+        }
+        let settled_at = settled_at.expect("never settled");
+        assert_eq!(bar_values, [0.8]);
+        // About one audio frame to get close, a few more until it is at rest.
+        assert!(settled_at > 10 && settled_at < 100, "{}ms", settled_at);
+
+        // After a while at rest the next frame starts from the bars shown.
+        let later = start + Duration::from_secs(10);
+        interpolator.push(&[0.2], later);
+        interpolator.sample(later + Duration::from_millis(1), &mut bar_values);
+        assert!(bar_values[0] > 0.7, "{:?}", bar_values);
+    }
+}
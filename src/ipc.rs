@@ -0,0 +1,340 @@
+use crate::app_config::is_hex_color;
+use calloop::generic::Generic;
+use calloop::{Interest, LoopHandle, Mode, PostAction};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+// Requests longer than this are dropped.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `$XDG_RUNTIME_DIR/wallpaper-cava.sock`
+pub fn socket_path() -> io::Result<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+    Ok(PathBuf::from(runtime_dir).join("wallpaper-cava.sock"))
+}
+
+/// A request, sent as one line of whitespace separated words.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    TogglePause,
+    Show,
+    Hide,
+    ToggleVisibility,
+    Sensitivity(f32),
+    Colors(Vec<String>),
+    Preset(String),
+    Reload,
+    Next,
+    State,
+}
+
+impl Command {
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let (name, args) = words.split_first().ok_or("Empty command")?;
+        let command = match (*name, args) {
+            ("pause", []) => Command::Pause,
+            ("resume", []) => Command::Resume,
+            ("toggle-pause", []) => Command::TogglePause,
+            ("show", []) => Command::Show,
+            ("hide", []) => Command::Hide,
+            ("toggle", []) => Command::ToggleVisibility,
+            ("sensitivity", [value]) => Command::Sensitivity(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|value: &f32| *value > 0.0)
+                    .ok_or_else(|| format!("Invalid sensitivity: {}", value))?,
+            ),
+            ("colors", colors) if !colors.is_empty() => {
+                if let Some(color) = colors.iter().find(|color| !is_hex_color(color)) {
+                    return Err(format!("Invalid color {}, expected #RRGGBB", color));
+                }
+                Command::Colors(colors.iter().map(|color| color.to_string()).collect())
+            }
+            ("preset", [name]) => Command::Preset(name.to_string()),
+            ("reload", []) => Command::Reload,
+            ("next", []) => Command::Next,
+            ("state", []) => Command::State,
+            _ => return Err(format!("Unknown command: {}", words.join(" "))),
+        };
+        Ok(command)
+    }
+}
+
+/// What `state` reports.
+#[derive(Serialize, Debug, Clone)]
+pub struct State {
+    pub paused: bool,
+    pub visible: bool,
+    pub silent: bool,
+    pub sensitivity: Option<f32>,
+    pub bpm: Option<f32>,
+    pub preset: Option<String>,
+    pub presets: Vec<String>,
+    pub bars: u32,
+    pub framerate: u32,
+    pub fps: f32,
+    pub width: u32,
+    pub height: u32,
+    pub colors: Vec<String>,
+}
+
+/// Sent back as one line of JSON.
+#[derive(Serialize, Debug, Clone)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<State>,
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply {
+            ok: true,
+            error: None,
+            state: None,
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Reply {
+            ok: false,
+            error: Some(error.into()),
+            state: None,
+        }
+    }
+
+    pub fn state(state: State) -> Self {
+        Reply {
+            ok: true,
+            error: None,
+            state: Some(state),
+        }
+    }
+}
+
+impl From<Result<(), String>> for Reply {
+    fn from(result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Reply::ok(),
+            Err(error) => Reply::error(error),
+        }
+    }
+}
+
+/// Removes the socket file when dropped.
+pub struct IpcServer {
+    path: PathBuf,
+}
+
+impl IpcServer {
+    /// Listens on `path` from the event loop behind `handle`, `handler` runs on
+    /// the event loop for every command. Fails if another instance is listening.
+    pub fn insert<'l, D: 'l>(
+        handle: &LoopHandle<'l, D>,
+        path: &Path,
+        handler: impl FnMut(Command, &mut D) -> Reply + 'l,
+    ) -> io::Result<Self> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another instance", path.display()),
+            ));
+        }
+        // Left behind by an instance that didn't shut down cleanly.
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let handler = Rc::new(RefCell::new(handler));
+        let connection_handle = handle.clone();
+        handle
+            .insert_source(
+                Generic::new(listener, Interest::READ, Mode::Level),
+                move |_, listener, _| {
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                if let Err(error) =
+                                    insert_connection(&connection_handle, stream, handler.clone())
+                                {
+                                    eprintln!("Failed to accept an IPC connection: {}", error);
+                                }
+                            }
+                            Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                            Err(error) => {
+                                eprintln!("Failed to accept an IPC connection: {}", error)
+                            }
+                        }
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .map_err(|error| io::Error::other(error.to_string()))?;
+        Ok(IpcServer {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Reads one line from `stream`, then replies and closes it.
+fn insert_connection<'l, D: 'l, H: FnMut(Command, &mut D) -> Reply + 'l>(
+    handle: &LoopHandle<'l, D>,
+    stream: UnixStream,
+    handler: Rc<RefCell<H>>,
+) -> io::Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut request = Vec::new();
+    handle
+        .insert_source(
+            Generic::new(stream, Interest::READ, Mode::Level),
+            move |_, stream, state| {
+                let mut chunk = [0u8; 4096];
+                let end_of_stream = loop {
+                    match (&**stream).read(&mut chunk) {
+                        Ok(0) => break true,
+                        Ok(size) => request.extend_from_slice(&chunk[..size]),
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => break false,
+                        Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                        Err(_) => return Ok(PostAction::Remove),
+                    }
+                };
+                let line_end = request.iter().position(|byte| *byte == b'\n');
+                if line_end.is_none() && !end_of_stream {
+                    if request.len() > MAX_REQUEST_SIZE {
+                        return Ok(PostAction::Remove);
+                    }
+                    return Ok(PostAction::Continue);
+                }
+                let line = String::from_utf8_lossy(&request[..line_end.unwrap_or(request.len())]);
+                let words: Vec<&str> = line.split_whitespace().collect();
+                let reply = match Command::parse(&words) {
+                    Ok(command) => (handler.borrow_mut())(command, state),
+                    Err(error) => Reply::error(error),
+                };
+                let mut reply = serde_json::to_string(&reply).unwrap();
+                reply.push('\n');
+                // The reply is small, a client that doesn't read it only stalls us briefly.
+                let _ = stream.set_nonblocking(false);
+                let _ = stream.set_write_timeout(Some(REPLY_TIMEOUT));
+                let _ = (&**stream).write_all(reply.as_bytes());
+                Ok(PostAction::Remove)
+            },
+        )
+        .map_err(|error| io::Error::other(error.to_string()))?;
+    Ok(())
+}
+
+/// Sends `words` as one command to the running instance and returns its reply.
+pub fn send(path: &Path, words: &[String]) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("{}\n", words.join(" ")).as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Command, String> {
+        Command::parse(&line.split_whitespace().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_every_command() {
+        for (line, command) in [
+            ("pause", Command::Pause),
+            ("resume", Command::Resume),
+            ("toggle-pause", Command::TogglePause),
+            ("show", Command::Show),
+            ("hide", Command::Hide),
+            ("toggle", Command::ToggleVisibility),
+            ("sensitivity 150", Command::Sensitivity(150.0)),
+            ("sensitivity 0.5", Command::Sensitivity(0.5)),
+            (
+                "colors #94e2d5 #F38BA8",
+                Command::Colors(vec!["#94e2d5".into(), "#F38BA8".into()]),
+            ),
+            ("preset night", Command::Preset("night".into())),
+            ("reload", Command::Reload),
+            ("next", Command::Next),
+            ("state", Command::State),
+        ] {
+            assert_eq!(parse(line), Ok(command), "{}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_argument_counts() {
+        for line in [
+            "pause now",
+            "resume 1",
+            "toggle-pause x",
+            "show all",
+            "hide all",
+            "toggle 2",
+            "sensitivity",
+            "sensitivity 1 2",
+            "colors",
+            "preset",
+            "preset a b",
+            "reload now",
+            "next 2",
+            "state all",
+        ] {
+            assert!(parse(line).is_err(), "{} was accepted", line);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for line in [
+            "sensitivity 0",
+            "sensitivity -10",
+            "sensitivity NaN",
+            "sensitivity loud",
+        ] {
+            assert_eq!(
+                parse(line),
+                Err(format!("Invalid sensitivity: {}", &line[12..])),
+                "{}",
+                line
+            );
+        }
+        for color in ["94e2d5", "#94e2d", "#94e2d5ff", "#94e2dg", "red"] {
+            assert_eq!(
+                parse(&format!("colors #ffffff {}", color)),
+                Err(format!("Invalid color {}, expected #RRGGBB", color))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_unknown_commands() {
+        assert_eq!(parse(""), Err("Empty command".into()));
+        assert_eq!(parse("   "), Err("Empty command".into()));
+        assert_eq!(parse("dance"), Err("Unknown command: dance".into()));
+        assert_eq!(parse("PAUSE"), Err("Unknown command: PAUSE".into()));
+    }
+}
@@ -0,0 +1,41 @@
+//! Core of wallpaper-cava, usable without the wallpaper binary.
+//!
+//! - [`app_config`]: the config file types.
+//! - [`appearance`]: colors and bar gap, and cross-fades between them.
+//! - [`background`]: the gradient and wallpaper image drawn behind the bars.
+//! - [`slideshow`]: cycles through the images of a directory as the wallpaper.
+//! - [`theme`]: imports colors from pywal, wallust, matugen and base16 palettes.
+//! - [`image_palette`]: derives colors from a wallpaper image.
+//! - [`audio`]: yields normalized bar values from cava or the built-in analyzer.
+//! - [`geometry`]: turns bar values into vertices.
+//! - [`postprocessing`]: smooths decoded bar values over time and across bars.
+//! - [`beat`]: finds beats in bar values and drives the effects they trigger.
+//! - [`ipc`]: the control socket and its client.
+//! - `dbus`: the same commands over D-Bus, with the `dbus` feature.
+//! - [`silence`]: fades the visualizer out while nothing is playing.
+//! - [`interpolation`]: smooths bar values between audio frames.
+//! - [`renderer::GlRenderer`]: draws into the GL context current on the calling thread.
+//! - [`egl_renderer`] and [`software`]: ready-made [`renderer::Renderer`]s for a Wayland surface.
+extern crate khronos_egl as egl;
+
+pub mod app_config;
+pub mod appearance;
+pub mod audio;
+pub mod background;
+pub mod beat;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod egl_renderer;
+pub mod geometry;
+pub mod gl_context;
+pub mod headless;
+pub mod image_palette;
+pub mod interpolation;
+pub mod ipc;
+pub mod postprocessing;
+pub mod renderer;
+pub mod shader;
+pub mod silence;
+pub mod slideshow;
+pub mod software;
+pub mod theme;
@@ -1,111 +1,251 @@
-extern crate khronos_egl as egl;
-
-use gl::types::{GLsizei, GLsizeiptr};
-use smithay_client_toolkit::reexports::calloop::EventLoop;
+use calloop::channel::{self, Event};
+use calloop::signals::{Signal, Signals};
+use calloop::timer::{TimeoutAction, Timer};
+use smithay_client_toolkit::reexports::calloop::ping::make_ping;
+use smithay_client_toolkit::reexports::calloop::{EventLoop, LoopSignal};
 use smithay_client_toolkit::reexports::calloop_wayland_source::WaylandSource;
 use smithay_client_toolkit::registry::ProvidesRegistryState;
 use smithay_client_toolkit::shell::wlr_layer::{
     Anchor, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
 };
+use smithay_client_toolkit::shm::{Shm, ShmHandler};
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
     output::{OutputHandler, OutputState},
     registry::RegistryState,
 };
 use smithay_client_toolkit::{
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, registry_handlers,
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
+    registry_handlers,
 };
 use wayland_client::protocol::wl_surface::WlSurface;
-use wayland_client::Proxy;
 use wayland_client::{
     globals::registry_queue_init,
     protocol::{wl_output, wl_surface},
     Connection, QueueHandle,
 };
-use wayland_egl::WlEglSurface;
-
-use core::{ffi, panic};
-use egl::API as egl;
-use std::ffi::CString;
-use std::io::Write;
-use std::process::{exit, ChildStdout};
-use std::{env, fs, ptr};
-use std::{
-    io::{BufReader, Read},
-    process::{Command, Stdio},
-    time::Duration,
-};
-
-pub mod app_config;
-use app_config::*;
-pub mod cli_help;
-use cli_help::*;
-use std::collections::HashMap;
 
-const VERTEX_SHADER_SRC: &str = include_str!("shaders/vertex_shader.glsl");
+use core::panic;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs, io};
 
-const FRAGMENT_SHADER_SRC: &str = include_str!("shaders/fragment_shader.glsl");
+mod cli_help;
+use cli_help::*;
+use wallpaper_cava::app_config::*;
+use wallpaper_cava::appearance::{Appearance, Crossfade};
+use wallpaper_cava::audio::recording::{Recorder, RecordingHeader};
+use wallpaper_cava::audio::{spawn_audio_source, AudioSource};
+use wallpaper_cava::background::{self, Background};
+use wallpaper_cava::beat::{BeatDetector, BeatEffects};
+#[cfg(feature = "dbus")]
+use wallpaper_cava::dbus::DbusService;
+use wallpaper_cava::egl_renderer::EglRenderer;
+use wallpaper_cava::headless::{render_to_png, RenderOptions};
+use wallpaper_cava::image_palette::ImagePalette;
+use wallpaper_cava::interpolation::Interpolator;
+use wallpaper_cava::ipc::{self, Command, IpcServer, Reply, State};
+use wallpaper_cava::postprocessing::Pipeline;
+use wallpaper_cava::renderer::Renderer;
+use wallpaper_cava::silence::Silence;
+use wallpaper_cava::slideshow::Slideshow;
+use wallpaper_cava::software::SoftwareRenderer;
+use wallpaper_cava::theme::{self, ImportWatcher};
+
+// How often the file of colors.import.file is checked for changes, and whether the
+// slideshow is due.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 fn main() {
     let mut config_filename = "config.toml";
     let args: Vec<String> = env::args().collect();
-    if args.len() == 3 {
-        if args[1] != "--config" {
-            print_help();
-            exit(0);
-        }
-        config_filename = &args[2];
-    } else if args.len() != 1 {
-        print_help();
-        exit(0);
-    }
-    let cava_output_config: HashMap<String, String> = HashMap::from([
-        ("method".into(), "raw".into()),
-        ("raw_target".into(), "/dev/stdout".into()),
-        ("bit_format".into(), "16bit".into()),
-    ]);
-    let config_str = fs::read_to_string(config_filename).expect("Unable to read config file");
-    let config: Config = match toml::from_str(&config_str) {
-        Ok(config) => config,
-        Err(error) => panic!("Error parsing config: {}", error.message()),
+    if args.len() > 1 && args[1] == "render" {
+        let options = match RenderOptions::from_args(&args[2..]) {
+            Ok(options) => options,
+            Err(error) => {
+                eprintln!("{}", error);
+                print_help();
+                exit(1);
+            }
+        };
+        let config = load_config(&options.config_filename, options.preset.as_deref());
+        if let Err(error) = render_to_png(&config, &options) {
+            eprintln!("{}", error);
+            exit(1);
+        }
+        return;
+    }
+    if args.len() > 1 && args[1] == "msg" {
+        send_command(&args[2..]);
+        return;
+    }
+    let mut record_filename = None;
+    let mut preset = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--config", Some(value)) => config_filename = value,
+            ("--record", Some(value)) => record_filename = Some(value.clone()),
+            ("--preset", Some(value)) => preset = Some(value.clone()),
+            _ => {
+                print_help();
+                exit(0);
+            }
+        }
+    }
+    let mut config = load_config(config_filename, preset.as_deref());
+    let slideshow = match Slideshow::new(&config.background, Instant::now()) {
+        Ok(slideshow) => slideshow,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+    let background = apply_slideshow_palette(&mut config, slideshow.as_ref())
+        .and_then(|()| load_background(&config, slideshow.as_ref()));
+    let background = match background {
+        Ok(background) => background,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
     };
-    let cava_config = CavaConfig {
-        general: CavaGeneralConfig {
+    // Holds None again once writing failed.
+    let recorder = Mutex::new(record_filename.map(|record_filename| {
+        let header = RecordingHeader {
+            bar_count: config.bars.amount,
             framerate: config.general.framerate,
-            bars: config.bars.amount,
-            autosens: config.general.autosens,
-            sensitivity: config.general.sensitivity,
-        },
-        smoothing: CavaSmoothingConfig {
-            monstercat: config.smoothing.monstercat,
-            waves: config.smoothing.waves,
-            noise_reduction: config.smoothing.noise_reduction,
-        },
-        output: cava_output_config,
+        };
+        match Recorder::create(&record_filename, header) {
+            Ok(recorder) => recorder,
+            Err(error) => {
+                eprintln!("Failed to create {}: {}", record_filename, error);
+                exit(1);
+            }
+        }
+    }));
+    let mut event_loop: EventLoop<AppState> =
+        EventLoop::try_new().expect("Failed to initialize the event loop!");
+    let loop_handle = event_loop.handle();
+    // Blocks the signals for this thread, so it has to happen before any other thread is spawned.
+    let signals = Signals::new(&[
+        Signal::SIGINT,
+        Signal::SIGTERM,
+        Signal::SIGHUP,
+        Signal::SIGUSR1,
+        Signal::SIGUSR2,
+    ])
+    .unwrap();
+    loop_handle
+        .insert_source(signals, |event, _, state: &mut AppState| {
+            let command = match event.signal() {
+                Signal::SIGHUP => Command::Reload,
+                Signal::SIGUSR1 => Command::ToggleVisibility,
+                Signal::SIGUSR2 => Command::TogglePause,
+                signal => {
+                    println!("Received {}, shutting down", signal);
+                    state.audio_source.shutdown();
+                    state.loop_signal.stop();
+                    return;
+                }
+            };
+            if let Some(error) = state.handle_command(command).error {
+                eprintln!("{}: {}", event.signal(), error);
+            }
+        })
+        .unwrap();
+    loop_handle
+        .insert_source(
+            Timer::from_duration(POLL_INTERVAL),
+            |_, _, state: &mut AppState| {
+                let due = state
+                    .slideshow
+                    .as_ref()
+                    .is_some_and(|slideshow| slideshow.is_due(Instant::now()));
+                if due {
+                    if let Err(error) = state.next_wallpaper() {
+                        eprintln!("Failed to show the next wallpaper: {}", error);
+                    }
+                }
+                let changed = state
+                    .import_watcher
+                    .as_mut()
+                    .is_some_and(|import_watcher| import_watcher.changed());
+                if changed {
+                    if let Err(error) = state.reload() {
+                        eprintln!("Failed to apply the changed colors: {}", error);
+                    }
+                }
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            },
+        )
+        .unwrap();
+    let (wallpapers, wallpaper_channel) = channel::channel::<Wallpaper>();
+    loop_handle
+        .insert_source(wallpaper_channel, |event, _, state: &mut AppState| {
+            if let Event::Msg(wallpaper) = event {
+                state.show_wallpaper(wallpaper);
+            }
+        })
+        .unwrap();
+    let (audio_ping, audio_ping_source) = make_ping().unwrap();
+    let on_frame: Arc<OnFrame> = Arc::new(move |bar_values: &[f32]| {
+        let mut recorder = recorder.lock().unwrap();
+        if let Some(Err(error)) = recorder
+            .as_mut()
+            .map(|recorder| recorder.write_frame(bar_values))
+        {
+            eprintln!("Stopped recording: {}", error);
+            *recorder = None;
+        }
+        drop(recorder);
+        audio_ping.ping();
+    });
+    let audio_source = match start_audio_source(&config, &on_frame) {
+        Ok(audio_source) => audio_source,
+        Err(error) => {
+            eprintln!("Failed to start the audio source: {}", error);
+            exit(1);
+        }
     };
-    let string_cava_config: String = toml::to_string(&cava_config).unwrap();
-    let mut cmd = Command::new("cava");
-    cmd.arg("-p").arg("/dev/stdin");
-    let cava_process = cmd
-        .stdout(Stdio::piped())
-        .stdin(Stdio::piped())
-        .spawn()
-        .expect("failed to spawn cava process");
-    let mut cava_stdin = cava_process.stdin.unwrap();
-    cava_stdin.write_all(string_cava_config.as_bytes()).unwrap();
-    drop(cava_stdin);
-    let cava_stdout = cava_process.stdout.unwrap();
-    let cava_reader = BufReader::new(cava_stdout);
     let conn = Connection::connect_to_env().unwrap();
     let (globals, event_queue) = registry_queue_init(&conn).unwrap();
     let qh = event_queue.handle();
-    let mut event_loop: EventLoop<AppState> =
-        EventLoop::try_new().expect("Failed to initialize the event loop!");
-    let loop_handle = event_loop.handle();
     WaylandSource::new(conn.clone(), event_queue)
-        .insert(loop_handle)
+        .insert(loop_handle.clone())
+        .unwrap();
+    loop_handle
+        .insert_source(audio_ping_source, |_, _, state: &mut AppState| {
+            state.audio_frame_ready()
+        })
         .unwrap();
-    let frame_duration = Duration::from_secs(1) / config.general.framerate;
+    let _ipc_server = match ipc::socket_path().and_then(|path| {
+        IpcServer::insert(&loop_handle, &path, |command, state: &mut AppState| {
+            state.handle_command(command)
+        })
+    }) {
+        Ok(ipc_server) => Some(ipc_server),
+        Err(error) => {
+            eprintln!("Control socket disabled: {}", error);
+            None
+        }
+    };
+    #[cfg(feature = "dbus")]
+    let dbus = match DbusService::start(&loop_handle, None, |command, state: &mut AppState| {
+        state.handle_command(command)
+    }) {
+        Ok(dbus) => {
+            dbus.set_preset(config.general.preset.as_deref());
+            Some(dbus)
+        }
+        Err(error) => {
+            eprintln!("D-Bus service disabled: {}", error);
+            None
+        }
+    };
     let compositor = CompositorState::bind(&globals, &qh).expect("wl_compositor not available");
     let surface = compositor.create_surface(&qh);
     let layer_shell = LayerShell::bind(&globals, &qh).expect("layer shell not available");
@@ -119,180 +259,21 @@ fn main() {
     layer_surface.set_size(256, 256);
     layer_surface.set_anchor(Anchor::TOP);
     surface.commit();
-    egl.bind_api(egl::OPENGL_API).unwrap();
-    let egl_display = unsafe {
-        egl.get_display(conn.display().id().as_ptr() as *mut std::ffi::c_void)
-            .unwrap()
-    };
-    egl.initialize(egl_display).unwrap();
-    const ATTRIBUTES: [i32; 9] = [
-        egl::RED_SIZE,
-        8,
-        egl::GREEN_SIZE,
-        8,
-        egl::BLUE_SIZE,
-        8,
-        egl::ALPHA_SIZE,
-        8,
-        egl::NONE,
-    ];
-
-    let egl_config = egl
-        .choose_first_config(egl_display, &ATTRIBUTES)
-        .unwrap()
-        .unwrap();
-    const CONTEXT_ATTRIBUTES: [i32; 7] = [
-        egl::CONTEXT_MAJOR_VERSION,
-        4,
-        egl::CONTEXT_MINOR_VERSION,
-        6,
-        egl::CONTEXT_OPENGL_PROFILE_MASK,
-        egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
-        egl::NONE,
-    ];
-
-    let egl_context = egl
-        .create_context(egl_display, egl_config, None, &CONTEXT_ATTRIBUTES)
-        .unwrap();
-
-    let wl_egl_surface = WlEglSurface::new(surface.id(), 256, 256).unwrap();
-    let egl_surface = unsafe {
-        egl.create_window_surface(
-            egl_display,
-            egl_config,
-            wl_egl_surface.ptr() as egl::NativeWindowType,
-            None,
-        )
-        .unwrap()
-    };
-    egl.make_current(
-        egl_display,
-        Some(egl_surface),
-        Some(egl_surface),
-        Some(egl_context),
-    )
-    .unwrap();
-    gl::load_with(|name| egl.get_proc_address(name).unwrap() as *const std::ffi::c_void);
-    let version = unsafe {
-        let data = gl::GetString(gl::VERSION) as *const i8;
-        CString::from_raw(data as *mut _).into_string().unwrap()
+    let shm = Shm::bind(&globals, &qh).expect("wl_shm not available");
+    let mut renderer: Box<dyn Renderer> = match config.render.backend {
+        RenderBackend::Gl => match EglRenderer::new(&conn, &surface, &config) {
+            Ok(renderer) => Box::new(renderer),
+            Err(error) => {
+                eprintln!(
+                    "EGL initialization failed, using software rendering: {}",
+                    error
+                );
+                Box::new(SoftwareRenderer::new(&shm, &config))
+            }
+        },
+        RenderBackend::Software => Box::new(SoftwareRenderer::new(&shm, &config)),
     };
-
-    println!("OpenGL version: {}", version);
-    println!("EGL version: {}", egl.version());
-    let vert_shader_source = CString::new(VERTEX_SHADER_SRC).unwrap();
-    let vert_shader = unsafe { gl::CreateShader(gl::VERTEX_SHADER) };
-    unsafe {
-        gl::ShaderSource(
-            vert_shader,
-            1,
-            &vert_shader_source.as_ptr(),
-            std::ptr::null(),
-        );
-        gl::CompileShader(vert_shader);
-    }
-    let frag_shader_source = CString::new(FRAGMENT_SHADER_SRC).unwrap();
-    let frag_shader = unsafe { gl::CreateShader(gl::FRAGMENT_SHADER) };
-    unsafe {
-        gl::ShaderSource(
-            frag_shader,
-            1,
-            &frag_shader_source.as_ptr(),
-            std::ptr::null(),
-        );
-        gl::CompileShader(frag_shader);
-    }
-
-    let shader_program = unsafe { gl::CreateProgram() };
-    unsafe {
-        gl::AttachShader(shader_program, vert_shader);
-        gl::AttachShader(shader_program, frag_shader);
-        gl::LinkProgram(shader_program);
-        let mut status = gl::FALSE as gl::types::GLint;
-        gl::GetProgramiv(shader_program, gl::LINK_STATUS, &mut status);
-        if status != 1 {
-            let mut error_log_size: gl::types::GLint = 0;
-            gl::GetProgramiv(shader_program, gl::INFO_LOG_LENGTH, &mut error_log_size);
-            let mut error_log: Vec<u8> = Vec::with_capacity(error_log_size as usize);
-            gl::GetProgramInfoLog(
-                shader_program,
-                error_log_size,
-                &mut error_log_size,
-                error_log.as_mut_ptr() as *mut _,
-            );
-
-            error_log.set_len(error_log_size as usize);
-            let log = String::from_utf8(error_log).unwrap();
-            panic!("{}", log);
-        }
-    }
-    let mut vbo = 0;
-    let mut vao = 0;
-    let mut ebo = 0;
-    let mut gradient_colors_ssbo = 0;
-    let gradient_colors_rgba: Vec<[f32; 4]> = config
-        .colors
-        .iter()
-        .map(|color| array_from_config_color((color.1).clone()))
-        .collect();
-
-    let gradient_colors_size = gradient_colors_rgba.len() as i32;
-    let mut buffer_data: Vec<u8> = (gradient_colors_size).to_le_bytes().to_vec();
-    buffer_data.extend([0, 0, 0, 0].repeat(3)); // Fix for vec4 alignment
-    for color in gradient_colors_rgba.iter() {
-        for color_value in color {
-            buffer_data.extend_from_slice(&color_value.to_le_bytes());
-        }
-    }
-
-    let mut indices: Vec<u16> = vec![0; config.bars.amount as usize * 6];
-    for i in 0..config.bars.amount as usize {
-        indices[i * 6] = i as u16 * 4;
-        indices[i * 6 + 1] = i as u16 * 4 + 1;
-        indices[i * 6 + 2] = i as u16 * 4 + 2;
-        indices[i * 6 + 3] = i as u16 * 4 + 1;
-        indices[i * 6 + 4] = i as u16 * 4 + 2;
-        indices[i * 6 + 5] = i as u16 * 4 + 3;
-    }
-
-    let window_size_string = CString::new("WindowSize").unwrap();
-    unsafe {
-        gl::GenVertexArrays(1, &mut vao);
-        gl::BindVertexArray(vao);
-        gl::GenBuffers(1, &mut vbo);
-        gl::GenBuffers(1, &mut ebo);
-        gl::GenBuffers(1, &mut gradient_colors_ssbo);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-        gl::BufferData(
-            gl::ELEMENT_ARRAY_BUFFER,
-            (indices.len() * std::mem::size_of::<u16>()) as gl::types::GLsizeiptr,
-            indices.as_ptr() as *const ffi::c_void,
-            gl::STATIC_DRAW,
-        );
-        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, gradient_colors_ssbo);
-        gl::BufferData(
-            gl::SHADER_STORAGE_BUFFER,
-            buffer_data.len() as GLsizeiptr,
-            buffer_data.as_ptr() as *const ffi::c_void,
-            gl::STATIC_DRAW,
-        );
-        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, gradient_colors_ssbo);
-        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
-        gl::VertexAttribPointer(
-            0,
-            2,
-            gl::FLOAT,
-            gl::FALSE,
-            (2 * std::mem::size_of::<f32>()) as gl::types::GLsizei,
-            std::ptr::null(),
-        );
-        gl::EnableVertexAttribArray(0);
-        gl::BindVertexArray(0);
-    }
-
-    let windows_size_location =
-        unsafe { gl::GetUniformLocation(shader_program, window_size_string.as_ptr()) };
+    renderer.set_background(background);
 
     let mut simple_window = AppState {
         registry_state: RegistryState::new(&globals),
@@ -302,28 +283,141 @@ fn main() {
         layer_shell,
         layer_surface,
         surface,
-        cava_reader,
-        wl_egl_surface,
-        egl_surface,
-        egl_config,
-        egl_context,
-        egl_display,
-        shader_program,
-        vao,
-        vbo,
-        windows_size_location,
-        bar_count: config.bars.amount,
-        bar_gap: config.bars.gap,
-        background_color: array_from_config_color(config.general.background_color),
-        preferred_output_name: config.general.preferred_output,
+        audio_source,
+        on_frame,
+        audio_frame: vec![0.0; config.bars.amount as usize],
+        postprocessing: Pipeline::new(
+            &config.postprocessing,
+            config.bars.amount as usize,
+            config.general.framerate,
+        ),
+        beat_detector: BeatDetector::new(
+            &config.beat,
+            config.bars.amount as usize,
+            config.general.framerate,
+        ),
+        beat_effects: BeatEffects::new(&config.beat),
+        silence: Silence::new(&config.silence),
+        interpolator: Interpolator::new(
+            config.render.interpolation,
+            config.bars.amount as usize,
+            config.general.framerate,
+        ),
+        bar_values: vec![0.0; config.bars.amount as usize],
+        shm,
+        renderer,
+        preferred_output_name: config.general.preferred_output.clone(),
         compositor,
+        qh,
+        loop_signal: event_loop.get_signal(),
+        configured: false,
+        frame_callback_pending: false,
+        bars_hidden: false,
+        paused: false,
+        visible: true,
+        drawn_frames: 0,
+        fps: 0.0,
+        fps_since: Instant::now(),
+        #[cfg(feature = "dbus")]
+        dbus,
+        config_filename: config_filename.to_string(),
+        preset,
+        appearance: Appearance::from_config(&config),
+        crossfade: None,
+        import_watcher: ImportWatcher::new(&config),
+        slideshow,
+        wallpapers,
+        wallpaper_generation: 0,
+        background_fade_start: None,
+        config,
     };
-    event_loop
-        .run(frame_duration, &mut simple_window, |_| {})
-        .unwrap();
+    event_loop.run(None, &mut simple_window, |_| {}).unwrap();
+    // Destroys the EGL context, the layer surface and cava, see AppState.
+    drop(simple_window);
+    let _ = conn.flush();
 }
 
+fn load_config(config_filename: &str, preset: Option<&str>) -> Config {
+    match try_load_config(config_filename, preset) {
+        Ok(config) => config,
+        Err(error) => panic!("{}", error),
+    }
+}
+
+fn try_load_config(config_filename: &str, preset: Option<&str>) -> Result<Config, String> {
+    let config_str = fs::read_to_string(config_filename)
+        .map_err(|error| format!("Unable to read config file: {}", error))?;
+    let mut config = Config::parse(&config_str, preset)?;
+    theme::apply_import(&mut config)?;
+    Ok(config)
+}
+
+// With a slideshow its current image takes the place of background.image.
+fn background_image(config: &Config, slideshow: Option<&Slideshow>) -> Option<PathBuf> {
+    match slideshow {
+        Some(slideshow) => Some(slideshow.current().to_path_buf()),
+        None => config.background.image.as_deref().map(expand_home),
+    }
+}
+
+fn load_background(
+    config: &Config,
+    slideshow: Option<&Slideshow>,
+) -> Result<Option<Background>, String> {
+    Background::with_image(config, background_image(config, slideshow).as_deref())
+}
+
+// colors.import.from_image = "background" follows the slideshow, apply_import can't know its image.
+fn apply_slideshow_palette(
+    config: &mut Config,
+    slideshow: Option<&Slideshow>,
+) -> Result<(), String> {
+    match slideshow {
+        Some(slideshow) if config.colors.from_image() == Some(theme::FROM_BACKGROUND) => {
+            theme::apply_image_palette(config, slideshow.current())
+        }
+        _ => Ok(()),
+    }
+}
+
+type OnFrame = dyn Fn(&[f32]) + Send + Sync;
+
+// The next image of the slideshow, decoded and composed off the event loop.
+struct Wallpaper {
+    // AppState::wallpaper_generation when it was asked for.
+    generation: u32,
+    decoded: Result<(Option<Background>, Option<ImagePalette>), String>,
+}
+
+fn start_audio_source(
+    config: &Config,
+    on_frame: &Arc<OnFrame>,
+) -> io::Result<Box<dyn AudioSource>> {
+    let on_frame = on_frame.clone();
+    spawn_audio_source(config, move |bar_values: &[f32]| on_frame(bar_values))
+}
+
+// `wallpaper-cava msg <command>`, prints the reply and fails if the command did.
+fn send_command(words: &[String]) {
+    let reply = match ipc::socket_path().and_then(|path| ipc::send(&path, words)) {
+        Ok(reply) => reply,
+        Err(error) => {
+            eprintln!("Failed to reach wallpaper-cava: {}", error);
+            exit(1);
+        }
+    };
+    println!("{}", reply);
+    let ok = serde_json::from_str::<serde_json::Value>(&reply)
+        .map(|reply| reply["ok"] == true)
+        .unwrap_or(false);
+    if !ok {
+        exit(1);
+    }
+}
+
+// Fields drop in order, the renderer has to go before the surface it draws to.
 struct AppState {
+    renderer: Box<dyn Renderer>,
     registry_state: RegistryState,
     output_state: OutputState,
     width: u32,
@@ -331,82 +425,397 @@ struct AppState {
     layer_shell: LayerShell,
     layer_surface: LayerSurface,
     surface: WlSurface,
-    cava_reader: BufReader<ChildStdout>,
-    wl_egl_surface: WlEglSurface,
-    egl_surface: egl::Surface,
-    egl_config: egl::Config,
-    egl_context: egl::Context,
-    egl_display: egl::Display,
-    shader_program: u32,
-    vao: u32,
-    vbo: u32,
-    windows_size_location: i32,
-    bar_count: u32,
-    bar_gap: f32,
-    background_color: [f32; 4],
+    audio_source: Box<dyn AudioSource>,
+    on_frame: Arc<OnFrame>,
+    audio_frame: Vec<f32>,
+    postprocessing: Pipeline,
+    beat_detector: BeatDetector,
+    beat_effects: BeatEffects,
+    silence: Silence,
+    interpolator: Interpolator,
+    bar_values: Vec<f32>,
+    shm: Shm,
     preferred_output_name: Option<String>,
     compositor: CompositorState,
+    qh: QueueHandle<AppState>,
+    loop_signal: LoopSignal,
+    configured: bool,
+    frame_callback_pending: bool,
+    // The last draw committed the background alone, after fading out or hiding.
+    bars_hidden: bool,
+    paused: bool,
+    visible: bool,
+    // Frames drawn since fps_since, fps is updated once a second while drawing.
+    drawn_frames: u32,
+    fps: f32,
+    fps_since: Instant,
+    #[cfg(feature = "dbus")]
+    dbus: Option<DbusService>,
+    config_filename: String,
+    // Chosen with --preset or the preset command, kept across reloads.
+    preset: Option<String>,
+    // What the renderer currently shows, the start of the next cross-fade.
+    appearance: Appearance,
+    crossfade: Option<Crossfade>,
+    import_watcher: Option<ImportWatcher>,
+    slideshow: Option<Slideshow>,
+    wallpapers: channel::Sender<Wallpaper>,
+    // Changes with every wallpaper asked for and whenever one still being
+    // decoded would be out of date.
+    wallpaper_generation: u32,
+    // Set while the renderer cross-fades to the next wallpaper.
+    background_fade_start: Option<Instant>,
+    config: Config,
 }
 
 impl AppState {
-    pub fn draw(&mut self, _conn: &Connection, qh: &QueueHandle<Self>) {
-        let mut cava_buffer: Vec<u8> = vec![0; self.bar_count as usize * 2];
-        let mut unpacked_data: Vec<f32> = vec![0.0; self.bar_count as usize];
-        self.cava_reader.read_exact(&mut cava_buffer).unwrap();
-        for (unpacked_data_index, i) in (0..cava_buffer.len()).step_by(2).enumerate() {
-            let num = u16::from_le_bytes([cava_buffer[i], cava_buffer[i + 1]]);
-            unpacked_data[unpacked_data_index] = (num as f32) / 65530.0;
-        }
-        let bar_width: f32 =
-            2.0 / (self.bar_count as f32 + (self.bar_count as f32 - 1.0) * self.bar_gap);
-        let bar_gap_width: f32 = bar_width * self.bar_gap;
-        let mut vertices: Vec<f32> = vec![0.0; self.bar_count as usize * 8];
-        let fwidth: f32 = self.width as f32;
-        let fheight: f32 = self.height as f32;
-        for i in 0..self.bar_count as usize {
-            let bar_height: f32 = 2.0 * unpacked_data[i] - 1.0;
-            vertices[i * 8] = bar_gap_width * i as f32 + bar_width * i as f32 - 1.0;
-            vertices[i * 8 + 1] = bar_height;
-            vertices[i * 8 + 2] = bar_gap_width * i as f32 + bar_width * (i + 1) as f32 - 1.0;
-            vertices[i * 8 + 3] = bar_height;
-            vertices[i * 8 + 4] = bar_gap_width * i as f32 + bar_width * i as f32 - 1.0;
-            vertices[i * 8 + 5] = -1.0;
-            vertices[i * 8 + 6] = bar_gap_width * i as f32 + bar_width * (i + 1) as f32 - 1.0;
-            vertices[i * 8 + 7] = -1.0;
-        }
-        unsafe {
-            gl::BindVertexArray(self.vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (vertices.len() * std::mem::size_of::<f32>()) as gl::types::GLsizeiptr,
-                vertices.as_ptr() as *const _,
-                gl::DYNAMIC_DRAW,
-            );
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::ClearColor(
-                self.background_color[0],
-                self.background_color[1],
-                self.background_color[2],
-                self.background_color[3],
-            );
-            gl::Clear(gl::COLOR_BUFFER_BIT);
-            gl::UseProgram(self.shader_program);
-            gl::Uniform2f(self.windows_size_location, fwidth, fheight);
-            gl::DrawElements(
-                gl::TRIANGLES,
-                (self.bar_count as usize * 3 * std::mem::size_of::<u16>()) as gl::types::GLsizei,
-                // I don't know why * 3 works here, I thought that it is supposed to be * 6, but it
-                // works, so I'll keep it like this for now.
-                gl::UNSIGNED_SHORT,
-                ptr::null(),
-            );
-            gl::BindVertexArray(0);
+    pub fn draw(&mut self, qh: &QueueHandle<Self>) {
+        let now = Instant::now();
+        self.interpolator.sample(now, &mut self.bar_values);
+        let effects = self.beat_effects.levels(now);
+        for value in self.bar_values.iter_mut() {
+            *value = (*value * (1.0 + effects.bump)).clamp(0.0, 1.0);
+        }
+        self.renderer.set_effects(effects);
+        if let Some(crossfade) = &self.crossfade {
+            self.appearance = crossfade.sample(now);
+            self.renderer.set_appearance(&self.appearance);
+            if crossfade.is_done(now) {
+                self.crossfade = None;
+            }
+        }
+        if let Some(start) = self.background_fade_start {
+            let duration = self.crossfade_duration().as_secs_f32();
+            let elapsed = now.saturating_duration_since(start).as_secs_f32();
+            let progress = if duration > 0.0 {
+                (elapsed / duration).min(1.0)
+            } else {
+                1.0
+            };
+            self.renderer.set_background_fade(progress);
+            if progress == 1.0 {
+                self.background_fade_start = None;
+            }
+        }
+        self.renderer.set_opacity(if self.visible {
+            self.silence.opacity(now)
+        } else {
+            0.0
+        });
+        self.bars_hidden = !self.visible || self.silence.is_asleep(now);
+        // Requested before the renderer commits so the callback belongs to this frame.
+        // The background alone stays until the music is back, so it needs no callback.
+        if !self.bars_hidden {
+            self.surface.frame(qh, self.surface.clone());
+            self.frame_callback_pending = true;
+        }
+        self.renderer.draw(&self.surface, &self.bar_values);
+        self.count_frame(now);
+    }
+
+    fn count_frame(&mut self, now: Instant) {
+        self.drawn_frames += 1;
+        let elapsed = now.saturating_duration_since(self.fps_since);
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.drawn_frames as f32 / elapsed.as_secs_f32();
+            self.drawn_frames = 0;
+            self.fps_since = now;
+            #[cfg(feature = "dbus")]
+            if let Some(dbus) = &self.dbus {
+                dbus.set_fps(self.fps as f64);
+            }
+        }
+    }
+
+    // Frames drawn per second, 0 when nothing was drawn for a while.
+    fn current_fps(&self) -> f32 {
+        if self.fps_since.elapsed() > Duration::from_secs(2) {
+            return 0.0;
+        }
+        self.fps
+    }
+
+    fn take_audio_frame(&mut self) {
+        // The latest frame waits in the audio source until resumed.
+        if self.paused {
+            return;
+        }
+        if self.audio_source.take_frame(&mut self.audio_frame) {
+            let now = Instant::now();
+            self.silence.process(&self.audio_frame, now);
+            // Before postprocessing, which would soften the kicks.
+            if self.beat_detector.process(&self.audio_frame, now) {
+                self.beat_effects.beat(now);
+                #[cfg(feature = "dbus")]
+                if let Some(dbus) = &self.dbus {
+                    dbus.beat(self.beat_detector.bpm(now));
+                }
+            }
+            self.postprocessing.process(&mut self.audio_frame, now);
+            self.interpolator.push(&self.audio_frame, now);
+        }
+    }
+
+    // Whether drawing now would show something different from the last frame.
+    fn is_animating(&self) -> bool {
+        let now = Instant::now();
+        if !self.visible || self.silence.is_asleep(now) {
+            return !self.bars_hidden;
+        }
+        if self.paused {
+            return false;
+        }
+        self.interpolator.is_animating()
+            || self.beat_effects.is_active(now)
+            || self.silence.is_fading(now)
+            || self.crossfade.is_some()
+            || self.background_fade_start.is_some()
+    }
+
+    // Draws now unless a pending frame callback will.
+    fn redraw(&mut self) {
+        if self.configured && !self.frame_callback_pending {
+            let qh = self.qh.clone();
+            self.draw(&qh);
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) -> Reply {
+        match command {
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::TogglePause => self.paused = !self.paused,
+            Command::Show => self.visible = true,
+            Command::Hide => self.visible = false,
+            Command::ToggleVisibility => self.visible = !self.visible,
+            Command::Sensitivity(sensitivity) => {
+                self.config.general.sensitivity = Some(sensitivity);
+                return self.restart_audio_source().into();
+            }
+            Command::Colors(colors) => {
+                self.config.colors.gradient_colors = theme::numbered_colors(colors);
+                self.fade_to(Appearance::from_config(&self.config));
+            }
+            Command::Preset(name) => {
+                let result = try_load_config(&self.config_filename, Some(&name))
+                    .and_then(|config| self.apply_config(config));
+                if result.is_ok() {
+                    // Only a preset that applied is kept for reloads.
+                    self.preset = Some(name);
+                }
+                return result.into();
+            }
+            Command::Reload => return self.reload().into(),
+            Command::Next => return self.next_wallpaper().into(),
+            Command::State => return Reply::state(self.state()),
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(dbus) = &self.dbus {
+            dbus.set_visible(self.visible);
+        }
+        self.redraw();
+        Reply::ok()
+    }
+
+    fn restart_audio_source(&mut self) -> Result<(), String> {
+        // Shut down first, the fifo backend's socket can only be bound once.
+        self.audio_source.shutdown();
+        self.audio_source = start_audio_source(&self.config, &self.on_frame)
+            .map_err(|error| format!("Failed to restart the audio source: {}", error))?;
+        Ok(())
+    }
+
+    // render.backend and general.preferred_output only change with a restart.
+    fn reload(&mut self) -> Result<(), String> {
+        let config = try_load_config(&self.config_filename, self.preset.as_deref())?;
+        self.apply_config(config)
+    }
+
+    // Cross-fades when nothing but the appearance changed, rebuilds everything otherwise.
+    fn apply_config(&mut self, mut config: Config) -> Result<(), String> {
+        // The slideshow goes on where it was unless its settings changed.
+        let new_slideshow = if config.background != self.config.background {
+            Some(Slideshow::new(&config.background, Instant::now())?)
+        } else {
+            None
+        };
+        let slideshow = match &new_slideshow {
+            Some(slideshow) => slideshow.as_ref(),
+            None => self.slideshow.as_ref(),
+        };
+        // Colors following the slideshow come with the wallpaper from decode_wallpaper,
+        // the current ones stay until then.
+        let follows_slideshow =
+            slideshow.is_some() && config.colors.from_image() == Some(theme::FROM_BACKGROUND);
+        let followed_slideshow = self.config.colors.from_image() == Some(theme::FROM_BACKGROUND);
+        if follows_slideshow {
+            config.colors.gradient_colors = self.config.colors.gradient_colors.clone();
+            config.general.background_color = self.config.general.background_color.clone();
+        }
+        if config.colors.import != self.config.colors.import {
+            self.import_watcher = ImportWatcher::new(&config);
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(dbus) = &self.dbus {
+            dbus.set_preset(config.general.preset.as_deref());
+        }
+        if self.config.only_appearance_differs(&config) {
+            self.fade_to(Appearance::from_config(&config));
+            self.config = config;
+            // The slideshow is the same, so are its colors unless they weren't followed.
+            if follows_slideshow && !followed_slideshow {
+                let image = background_image(&self.config, self.slideshow.as_ref());
+                self.decode_wallpaper(image);
+            }
+            return Ok(());
+        }
+        if let Some(slideshow) = new_slideshow {
+            self.slideshow = slideshow;
+        }
+        let bar_count = config.bars.amount as usize;
+        self.audio_frame = vec![0.0; bar_count];
+        self.bar_values = vec![0.0; bar_count];
+        self.postprocessing =
+            Pipeline::new(&config.postprocessing, bar_count, config.general.framerate);
+        self.beat_detector = BeatDetector::new(&config.beat, bar_count, config.general.framerate);
+        self.beat_effects = BeatEffects::new(&config.beat);
+        self.silence = Silence::new(&config.silence);
+        self.interpolator = Interpolator::new(
+            config.render.interpolation,
+            bar_count,
+            config.general.framerate,
+        );
+        // Keeps the current background until decode_wallpaper replaces it.
+        self.renderer.reload(&config);
+        self.background_fade_start = None;
+        self.appearance = Appearance::from_config(&config);
+        self.crossfade = None;
+        self.config = config;
+        let image = background_image(&self.config, self.slideshow.as_ref());
+        self.decode_wallpaper(image);
+        let restarted = self.restart_audio_source();
+        self.redraw();
+        restarted
+    }
+
+    fn fade_to(&mut self, appearance: Appearance) {
+        self.crossfade = Some(Crossfade::new(
+            self.appearance.clone(),
+            appearance,
+            Instant::now(),
+            self.crossfade_duration(),
+        ));
+        self.redraw();
+    }
+
+    fn crossfade_duration(&self) -> Duration {
+        Duration::from_secs_f32(self.config.render.crossfade.unwrap_or(0.5).max(0.0))
+    }
+
+    fn next_wallpaper(&mut self) -> Result<(), String> {
+        let Some(slideshow) = &mut self.slideshow else {
+            return Err("No slideshow, background.directory is not set".to_string());
+        };
+        let path = slideshow.advance(Instant::now()).to_path_buf();
+        self.decode_wallpaper(Some(path));
+        Ok(())
+    }
+
+    // Builds the background around `image` on another thread, with its colors for
+    // colors.import.from_image = "background", and show_wallpaper shows it.
+    fn decode_wallpaper(&mut self, image: Option<PathBuf>) {
+        let background_config = self.config.background.clone();
+        let with_palette = self.config.colors.from_image() == Some(theme::FROM_BACKGROUND);
+        let (width, height) = (self.width, self.height);
+        // Only the latest request is shown, they may finish out of order.
+        self.wallpaper_generation += 1;
+        let generation = self.wallpaper_generation;
+        let wallpapers = self.wallpapers.clone();
+        thread::spawn(move || {
+            let image = image.map(|path| {
+                background::decode(&path)
+                    .map_err(|error| format!("Failed to show {}: {}", path.display(), error))
+            });
+            let decoded = image.transpose().map(|image| {
+                let palette = match &image {
+                    Some(image) if with_palette => ImagePalette::from_image(image),
+                    _ => None,
+                };
+                let mut background = Background::from_image(&background_config, image);
+                if let Some(background) = &mut background {
+                    background.prepare(width, height);
+                }
+                (background, palette)
+            });
+            // Only fails once the event loop is gone.
+            let _ = wallpapers.send(Wallpaper {
+                generation,
+                decoded,
+            });
+        });
+    }
+
+    // Cross-fades to a decoded wallpaper, and to its colors with
+    // colors.import.from_image = "background".
+    fn show_wallpaper(&mut self, wallpaper: Wallpaper) {
+        if wallpaper.generation != self.wallpaper_generation {
+            return;
+        }
+        let (background, palette) = match wallpaper.decoded {
+            Ok(decoded) => decoded,
+            Err(error) => {
+                eprintln!("{}", error);
+                return;
+            }
+        };
+        self.renderer.fade_background(background);
+        self.background_fade_start = Some(Instant::now());
+        let from_background = self.config.colors.from_image() == Some(theme::FROM_BACKGROUND);
+        if let Some(palette) = palette.filter(|_| from_background) {
+            theme::apply_palette(&mut self.config, palette);
+            self.fade_to(Appearance::from_config(&self.config));
+        }
+        self.redraw();
+    }
+
+    fn state(&self) -> State {
+        let now = Instant::now();
+        State {
+            paused: self.paused,
+            visible: self.visible,
+            silent: self.silence.is_asleep(now),
+            sensitivity: self.config.general.sensitivity,
+            bpm: self.beat_detector.bpm(now),
+            preset: self.config.general.preset.clone(),
+            presets: self
+                .config
+                .preset_names()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            bars: self.config.bars.amount,
+            framerate: self.config.general.framerate,
+            fps: self.current_fps(),
+            width: self.width,
+            height: self.height,
+            colors: self
+                .config
+                .colors
+                .gradient_colors
+                .values()
+                .map(|color| color.hex().to_string())
+                .collect(),
+        }
+    }
+
+    // Called from the event loop whenever the audio source produced a frame.
+    // While a frame callback is pending the frame is drawn once it fires instead.
+    fn audio_frame_ready(&mut self) {
+        self.take_audio_frame();
+        if self.configured && !self.frame_callback_pending && self.is_animating() {
+            let qh = self.qh.clone();
+            self.draw(&qh);
         }
-        egl.swap_buffers(self.egl_display, self.egl_surface)
-            .unwrap();
-        self.surface.frame(qh, self.surface.clone());
     }
 }
 
@@ -436,6 +845,8 @@ impl OutputHandler for AppState {
         if need_configuration {
             let old_surface = self.surface.clone();
             self.surface = self.compositor.create_surface(qh);
+            self.configured = false;
+            self.frame_callback_pending = false;
             self.layer_surface = self.layer_shell.create_layer_surface(
                 qh,
                 self.surface.clone(),
@@ -473,11 +884,18 @@ impl OutputHandler for AppState {
 }
 
 delegate_compositor!(AppState);
+delegate_shm!(AppState);
 
 delegate_output!(AppState);
 delegate_registry!(AppState);
 delegate_layer!(AppState);
 
+impl ShmHandler for AppState {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
 impl ProvidesRegistryState for AppState {
     fn registry(&mut self) -> &mut RegistryState {
         &mut self.registry_state
@@ -506,12 +924,16 @@ impl CompositorHandler for AppState {
 
     fn frame(
         &mut self,
-        conn: &Connection,
+        _conn: &Connection,
         qh: &QueueHandle<Self>,
         _surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
-        self.draw(conn, qh);
+        self.frame_callback_pending = false;
+        // Keeps drawing at the compositor's rate while the bars move.
+        if self.is_animating() {
+            self.draw(qh);
+        }
     }
 
     fn surface_enter(
@@ -552,30 +974,10 @@ impl LayerShellHandler for AppState {
         );
         self.width = width;
         self.height = height;
-        egl.destroy_surface(self.egl_display, self.egl_surface)
-            .unwrap();
-        self.wl_egl_surface =
-            WlEglSurface::new(self.surface.id(), self.width as i32, self.height as i32).unwrap();
-        self.egl_surface = unsafe {
-            egl.create_window_surface(
-                self.egl_display,
-                self.egl_config,
-                self.wl_egl_surface.ptr() as egl::NativeWindowType,
-                None,
-            )
-            .unwrap()
-        };
-        egl.make_current(
-            self.egl_display,
-            Some(self.egl_surface),
-            Some(self.egl_surface),
-            Some(self.egl_context),
-        )
-        .unwrap();
-        unsafe {
-            gl::Viewport(0, 0, self.width as GLsizei, self.height as GLsizei);
-        }
-        self.draw(_conn, qh);
+        self.renderer.configure(&self.surface, width, height);
+        self.configured = true;
+        self.take_audio_frame();
+        self.draw(qh);
         println!("configure finished");
     }
 }
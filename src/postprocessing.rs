@@ -0,0 +1,305 @@
+use crate::app_config::{PostprocessingConfig, ResponseCurve};
+use std::time::{Duration, Instant};
+
+/// wallpaper-cava's own smoothing of decoded bar values, independent of the
+/// audio source. Stages run in this order and each is skipped unless configured:
+/// response curve, monstercat, gaussian blur, attack/decay and gravity.
+pub struct Pipeline {
+    db_curve: Option<DbCurve>,
+    monstercat: Option<f32>,
+    gaussian: Option<GaussianBlur>,
+    attack_decay: Option<AttackDecay>,
+    gravity: Option<Gravity>,
+    frame_duration: Duration,
+    last_frame: Option<Instant>,
+}
+
+impl Pipeline {
+    /// `framerate` is the rate audio frames arrive at, used for the first frame.
+    pub fn new(config: &PostprocessingConfig, bar_count: usize, framerate: u32) -> Self {
+        Pipeline {
+            db_curve: match config.curve {
+                ResponseCurve::Linear => None,
+                ResponseCurve::Db => Some(DbCurve::new(config.db_range.unwrap_or(60.0))),
+            },
+            monstercat: config.monstercat.filter(|monstercat| *monstercat > 1.0),
+            gaussian: config
+                .gaussian
+                .filter(|sigma| *sigma > 0.0)
+                .map(GaussianBlur::new),
+            attack_decay: match (config.attack, config.decay) {
+                (None, None) => None,
+                (attack, decay) => Some(AttackDecay::new(
+                    attack.unwrap_or(0.0),
+                    decay.unwrap_or(0.0),
+                    bar_count,
+                )),
+            },
+            gravity: config
+                .gravity
+                .filter(|gravity| *gravity > 0.0)
+                .map(|gravity| Gravity::new(gravity, bar_count)),
+            frame_duration: Duration::from_secs(1) / framerate.max(1),
+            last_frame: None,
+        }
+    }
+
+    /// Processes the frame that arrived at `now` in place.
+    pub fn process(&mut self, bar_values: &mut [f32], now: Instant) {
+        let dt = match self.last_frame {
+            Some(last_frame) => now.saturating_duration_since(last_frame),
+            None => self.frame_duration,
+        }
+        .as_secs_f32();
+        self.last_frame = Some(now);
+        if let Some(db_curve) = &self.db_curve {
+            db_curve.apply(bar_values);
+        }
+        if let Some(monstercat) = self.monstercat {
+            monstercat_smoothing(bar_values, monstercat);
+        }
+        if let Some(gaussian) = &mut self.gaussian {
+            gaussian.apply(bar_values);
+        }
+        if let Some(attack_decay) = &mut self.attack_decay {
+            attack_decay.apply(bar_values, dt);
+        }
+        if let Some(gravity) = &mut self.gravity {
+            gravity.apply(bar_values, dt);
+        }
+    }
+}
+
+/// Maps linear amplitudes to decibels, showing the top `range` dB.
+pub struct DbCurve {
+    range: f32,
+}
+
+impl DbCurve {
+    pub fn new(range: f32) -> Self {
+        DbCurve {
+            range: range.max(f32::EPSILON),
+        }
+    }
+
+    pub fn apply(&self, bar_values: &mut [f32]) {
+        for value in bar_values.iter_mut() {
+            *value = if *value > 0.0 {
+                ((20.0 * value.log10() + self.range) / self.range).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+/// Lets every bar pull its neighbours up to its height divided by `monstercat`
+/// per bar of distance, like cava's monstercat smoothing.
+pub fn monstercat_smoothing(bar_values: &mut [f32], monstercat: f32) {
+    for z in 0..bar_values.len() {
+        for m in 0..bar_values.len() {
+            if m == z {
+                continue;
+            }
+            let distance = (z as i32 - m as i32).abs();
+            bar_values[m] = bar_values[m].max(bar_values[z] / monstercat.powi(distance));
+        }
+    }
+}
+
+/// Blurs across bars with a gaussian kernel, `sigma` is in bars. At the edges
+/// the kernel is renormalized, so flat input stays flat.
+pub struct GaussianBlur {
+    kernel: Vec<f32>,
+    blurred: Vec<f32>,
+}
+
+impl GaussianBlur {
+    pub fn new(sigma: f32) -> Self {
+        let radius = (sigma * 3.0).ceil() as i32;
+        let kernel = (-radius..=radius)
+            .map(|offset| (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp())
+            .collect();
+        GaussianBlur {
+            kernel,
+            blurred: Vec::new(),
+        }
+    }
+
+    pub fn apply(&mut self, bar_values: &mut [f32]) {
+        let radius = (self.kernel.len() / 2) as isize;
+        self.blurred.clear();
+        for i in 0..bar_values.len() as isize {
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for (k, weight) in self.kernel.iter().enumerate() {
+                let j = i + k as isize - radius;
+                if j >= 0 && (j as usize) < bar_values.len() {
+                    sum += bar_values[j as usize] * weight;
+                    weight_sum += weight;
+                }
+            }
+            self.blurred.push(sum / weight_sum);
+        }
+        bar_values.copy_from_slice(&self.blurred);
+    }
+}
+
+/// Exponential smoothing over time with separate time constants in seconds for
+/// rising (attack) and falling (decay) bars. 0 follows immediately.
+pub struct AttackDecay {
+    attack: f32,
+    decay: f32,
+    current: Vec<f32>,
+}
+
+impl AttackDecay {
+    pub fn new(attack: f32, decay: f32, bar_count: usize) -> Self {
+        AttackDecay {
+            attack,
+            decay,
+            current: vec![0.0; bar_count],
+        }
+    }
+
+    /// `dt` is the time since the last frame in seconds.
+    pub fn apply(&mut self, bar_values: &mut [f32], dt: f32) {
+        for (value, current) in bar_values.iter_mut().zip(self.current.iter_mut()) {
+            let time_constant = if *value > *current {
+                self.attack
+            } else {
+                self.decay
+            };
+            if time_constant <= 0.0 {
+                *current = *value;
+            } else {
+                *current += (*value - *current) * (1.0 - (-dt / time_constant).exp());
+            }
+            *value = *current;
+        }
+    }
+}
+
+/// Bars jump up immediately and fall with constant `acceleration`, in bar
+/// heights per second squared, until they meet the input again.
+pub struct Gravity {
+    acceleration: f32,
+    current: Vec<f32>,
+    velocity: Vec<f32>,
+}
+
+impl Gravity {
+    pub fn new(acceleration: f32, bar_count: usize) -> Self {
+        Gravity {
+            acceleration,
+            current: vec![0.0; bar_count],
+            velocity: vec![0.0; bar_count],
+        }
+    }
+
+    /// `dt` is the time since the last frame in seconds.
+    pub fn apply(&mut self, bar_values: &mut [f32], dt: f32) {
+        for ((value, current), velocity) in bar_values
+            .iter_mut()
+            .zip(self.current.iter_mut())
+            .zip(self.velocity.iter_mut())
+        {
+            if *value >= *current {
+                *current = *value;
+                *velocity = 0.0;
+            } else {
+                *velocity += self.acceleration * dt;
+                *current = (*current - *velocity * dt).max(*value);
+            }
+            *value = *current;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual_value, expected_value) in actual.iter().zip(expected) {
+            assert!(
+                (actual_value - expected_value).abs() < 1e-5,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn db_curve_maps_the_range_to_0_1() {
+        let mut bar_values = [1.0, 0.1, 0.001, 0.0001, 0.0];
+        DbCurve::new(60.0).apply(&mut bar_values);
+        assert_close(&bar_values, &[1.0, 2.0 / 3.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn monstercat_lifts_neighbours() {
+        let mut bar_values = [0.0, 0.0, 1.0, 0.0, 0.9];
+        monstercat_smoothing(&mut bar_values, 2.0);
+        assert_close(&bar_values, &[0.25, 0.5, 1.0, 0.5, 0.9]);
+    }
+
+    #[test]
+    fn gaussian_blur_keeps_flat_input_and_spreads_peaks() {
+        let mut blur = GaussianBlur::new(1.0);
+        let mut flat = [0.5; 6];
+        blur.apply(&mut flat);
+        assert_close(&flat, &[0.5; 6]);
+
+        let mut peak = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+        blur.apply(&mut peak);
+        assert!(peak[3] < 1.0);
+        assert_close(&[peak[2]], &[peak[4]]);
+        assert!(peak[2] > peak[1] && peak[1] > peak[0] && peak[0] > 0.0);
+    }
+
+    #[test]
+    fn attack_and_decay_use_their_own_time_constants() {
+        let mut attack_decay = AttackDecay::new(0.0, 1.0, 2);
+        let mut bar_values = [1.0, 0.5];
+        attack_decay.apply(&mut bar_values, 0.1);
+        assert_close(&bar_values, &[1.0, 0.5]);
+
+        let mut bar_values = [0.0, 0.0];
+        attack_decay.apply(&mut bar_values, 1.0);
+        let remaining = (-1.0f32).exp();
+        assert_close(&bar_values, &[remaining, 0.5 * remaining]);
+    }
+
+    #[test]
+    fn gravity_falls_with_constant_acceleration() {
+        let mut gravity = Gravity::new(2.0, 1);
+        let mut bar_values = [1.0];
+        gravity.apply(&mut bar_values, 0.1);
+        assert_close(&bar_values, &[1.0]);
+
+        // v = 0.2, falls 0.02, then v = 0.4, falls 0.04.
+        let mut bar_values = [0.0];
+        gravity.apply(&mut bar_values, 0.1);
+        assert_close(&bar_values, &[0.98]);
+        let mut bar_values = [0.0];
+        gravity.apply(&mut bar_values, 0.1);
+        assert_close(&bar_values, &[0.94]);
+
+        // Stops at the input and jumps back up.
+        let mut bar_values = [0.95];
+        gravity.apply(&mut bar_values, 0.1);
+        assert_close(&bar_values, &[0.95]);
+    }
+
+    #[test]
+    fn pipeline_skips_unconfigured_stages() {
+        let config = PostprocessingConfig::default();
+        let mut pipeline = Pipeline::new(&config, 3, 60);
+        let mut bar_values = [0.1, 0.7, 0.3];
+        pipeline.process(&mut bar_values, Instant::now());
+        assert_close(&bar_values, &[0.1, 0.7, 0.3]);
+    }
+}
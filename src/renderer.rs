@@ -0,0 +1,462 @@
+use crate::app_config::Config;
+use crate::appearance::Appearance;
+use crate::background::Background;
+use crate::beat::EffectLevels;
+use crate::geometry::BarLayout;
+use crate::gl_context::GlApi;
+use crate::shader::{create_program, ShaderError};
+use core::ffi;
+use gl::types::{GLsizei, GLsizeiptr};
+use std::ffi::CString;
+use std::ptr;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+const VERTEX_SHADER_SRC: &str = include_str!("shaders/vertex_shader.glsl");
+
+const FRAGMENT_SHADER_SRC: &str = include_str!("shaders/fragment_shader.glsl");
+
+// Used on contexts without SSBO support, the `#version` line is prepended at runtime.
+const FALLBACK_VERTEX_SHADER_SRC: &str = include_str!("shaders/fallback_vertex_shader.glsl");
+
+const FALLBACK_FRAGMENT_SHADER_SRC: &str = include_str!("shaders/fallback_fragment_shader.glsl");
+
+// Work with every context, the `#version` line is prepended at runtime like for the fallback shaders.
+const BACKGROUND_VERTEX_SHADER_SRC: &str = include_str!("shaders/background_vertex_shader.glsl");
+
+const BACKGROUND_FRAGMENT_SHADER_SRC: &str =
+    include_str!("shaders/background_fragment_shader.glsl");
+
+// Must match MAX_GRADIENT_COLORS in fallback_fragment_shader.glsl
+const MAX_FALLBACK_GRADIENT_COLORS: usize = 32;
+
+/// Backend `AppState` draws through. Implementations present the frame on the
+/// surface themselves, so `draw` ends with a commit.
+pub trait Renderer {
+    /// Called on every layer surface configure, `surface` may differ from the
+    /// one passed before when the output changed.
+    fn configure(&mut self, surface: &WlSurface, width: u32, height: u32);
+
+    /// Used by every following `draw`.
+    fn set_effects(&mut self, effects: EffectLevels);
+
+    /// Fades the bars and the flash, 0.0 leaves the background alone.
+    fn set_opacity(&mut self, opacity: f32);
+
+    /// Picks up new bars, colors and background color from `config`. The
+    /// background layer stays until `set_background` or `fade_background`.
+    fn reload(&mut self, config: &Config);
+
+    /// Drawn over the background color, composed again whenever the size changes.
+    fn set_background(&mut self, background: Option<Background>);
+
+    /// Like `set_background`, but the current background stays mixed in until
+    /// `set_background_fade` reaches 1.0.
+    fn fade_background(&mut self, background: Option<Background>);
+
+    /// 0.0 shows the background before `fade_background`, 1.0 the new one.
+    fn set_background_fade(&mut self, progress: f32);
+
+    /// Cheaper than `reload`, used for every step of a cross-fade.
+    fn set_appearance(&mut self, appearance: &Appearance);
+
+    /// `bar_values` are normalized to 0.0..=1.0.
+    fn draw(&mut self, surface: &WlSurface, bar_values: &[f32]);
+}
+
+/// Owns every GL object needed to draw the bars. It draws into whatever
+/// surface is current, so it works the same for a layer surface and a pbuffer.
+pub struct GlRenderer {
+    api: GlApi,
+    shader_program: u32,
+    vao: u32,
+    vbo: u32,
+    ebo: u32,
+    gradient_colors_ssbo: u32,
+    windows_size_location: i32,
+    gradient_shift_location: i32,
+    glow_location: i32,
+    opacity_location: i32,
+    background_program: u32,
+    background_vao: u32,
+    background_flash_location: i32,
+    background_fade_location: i32,
+    background_color_location: i32,
+    background: BackgroundTexture,
+    previous_background: BackgroundTexture,
+    background_fade: f32,
+    layout: BarLayout,
+    background_color: [f32; 4],
+    effects: EffectLevels,
+    opacity: f32,
+}
+
+impl GlRenderer {
+    /// Requires a current context created for `api`, with `gl::load_with` already
+    /// run for it, since the `gl` crate's function pointers are global.
+    pub fn new(api: GlApi, config: &Config) -> Result<Self, ShaderError> {
+        let shader_program = if api.supports_ssbo() {
+            create_program(VERTEX_SHADER_SRC, FRAGMENT_SHADER_SRC)?
+        } else {
+            let header = api.fallback_shader_header();
+            create_program(
+                &format!("{}{}", header, FALLBACK_VERTEX_SHADER_SRC),
+                &format!("{}{}", header, FALLBACK_FRAGMENT_SHADER_SRC),
+            )?
+        };
+        let header = api.fallback_shader_header();
+        let background_program = create_program(
+            &format!("{}{}", header, BACKGROUND_VERTEX_SHADER_SRC),
+            &format!("{}{}", header, BACKGROUND_FRAGMENT_SHADER_SRC),
+        )
+        .inspect_err(|_| unsafe { gl::DeleteProgram(shader_program) })?;
+        let mut vbo = 0;
+        let mut vao = 0;
+        let mut ebo = 0;
+        let mut gradient_colors_ssbo = 0;
+        let appearance = Appearance::from_config(config);
+        if !api.supports_ssbo() && appearance.gradient_colors.len() > MAX_FALLBACK_GRADIENT_COLORS {
+            eprintln!(
+                "{} supports at most {} gradient colors, ignoring the rest",
+                api, MAX_FALLBACK_GRADIENT_COLORS
+            );
+        }
+
+        let layout = BarLayout::new(config.bars.amount, config.bars.gap);
+        let indices = layout.indices();
+
+        let window_size_string = CString::new("WindowSize").unwrap();
+        let gradient_shift_string = CString::new("gradient_shift").unwrap();
+        let glow_string = CString::new("glow").unwrap();
+        let opacity_string = CString::new("opacity").unwrap();
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::GenBuffers(1, &mut ebo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                (indices.len() * std::mem::size_of::<u16>()) as GLsizeiptr,
+                indices.as_ptr() as *const ffi::c_void,
+                gl::STATIC_DRAW,
+            );
+            if api.supports_ssbo() {
+                gl::GenBuffers(1, &mut gradient_colors_ssbo);
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, gradient_colors_ssbo);
+            }
+            gl::VertexAttribPointer(
+                0,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                (2 * std::mem::size_of::<f32>()) as GLsizei,
+                std::ptr::null(),
+            );
+            gl::EnableVertexAttribArray(0);
+            gl::BindVertexArray(0);
+        }
+        let mut background_vao = 0;
+        // Core profiles draw nothing without a bound VAO, even an empty one.
+        unsafe { gl::GenVertexArrays(1, &mut background_vao) };
+        let flash_string = CString::new("flash").unwrap();
+        let fade_string = CString::new("fade").unwrap();
+        let background_color_string = CString::new("background_color").unwrap();
+        let previous_background_string = CString::new("previous_background").unwrap();
+        let background_flash_location =
+            unsafe { gl::GetUniformLocation(background_program, flash_string.as_ptr()) };
+        let background_fade_location =
+            unsafe { gl::GetUniformLocation(background_program, fade_string.as_ptr()) };
+        let background_color_location =
+            unsafe { gl::GetUniformLocation(background_program, background_color_string.as_ptr()) };
+        unsafe {
+            // `background` stays on texture unit 0.
+            gl::UseProgram(background_program);
+            gl::Uniform1i(
+                gl::GetUniformLocation(background_program, previous_background_string.as_ptr()),
+                1,
+            );
+            gl::UseProgram(0);
+        }
+
+        let windows_size_location =
+            unsafe { gl::GetUniformLocation(shader_program, window_size_string.as_ptr()) };
+        let gradient_shift_location =
+            unsafe { gl::GetUniformLocation(shader_program, gradient_shift_string.as_ptr()) };
+        let glow_location = unsafe { gl::GetUniformLocation(shader_program, glow_string.as_ptr()) };
+        let opacity_location =
+            unsafe { gl::GetUniformLocation(shader_program, opacity_string.as_ptr()) };
+
+        let mut renderer = GlRenderer {
+            api,
+            shader_program,
+            vao,
+            vbo,
+            ebo,
+            gradient_colors_ssbo,
+            windows_size_location,
+            gradient_shift_location,
+            glow_location,
+            opacity_location,
+            background_program,
+            background_vao,
+            background_flash_location,
+            background_fade_location,
+            background_color_location,
+            background: BackgroundTexture::new(),
+            previous_background: BackgroundTexture::new(),
+            background_fade: 1.0,
+            layout,
+            background_color: appearance.background_color,
+            effects: EffectLevels::default(),
+            opacity: 1.0,
+        };
+        renderer.set_appearance(&appearance);
+        Ok(renderer)
+    }
+
+    pub fn set_appearance(&mut self, appearance: &Appearance) {
+        self.background_color = appearance.background_color;
+        self.layout.bar_gap = appearance.bar_gap;
+        let mut gradient_colors = appearance.gradient_colors.as_slice();
+        if !self.api.supports_ssbo() {
+            gradient_colors =
+                &gradient_colors[..gradient_colors.len().min(MAX_FALLBACK_GRADIENT_COLORS)];
+            upload_gradient_uniforms(self.shader_program, gradient_colors);
+            return;
+        }
+        let mut buffer_data: Vec<u8> = (gradient_colors.len() as i32).to_le_bytes().to_vec();
+        buffer_data.extend([0, 0, 0, 0].repeat(3)); // Fix for vec4 alignment
+        for color in gradient_colors {
+            for color_value in color {
+                buffer_data.extend_from_slice(&color_value.to_le_bytes());
+            }
+        }
+        unsafe {
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.gradient_colors_ssbo);
+            gl::BufferData(
+                gl::SHADER_STORAGE_BUFFER,
+                buffer_data.len() as GLsizeiptr,
+                buffer_data.as_ptr() as *const ffi::c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+    }
+
+    pub fn set_effects(&mut self, effects: EffectLevels) {
+        self.effects = effects;
+    }
+
+    pub fn set_background(&mut self, background: Option<Background>) {
+        self.background.set(background);
+        self.previous_background.set(None);
+        self.background_fade = 1.0;
+    }
+
+    /// Leaves no background layer behind, for a renderer taking this one's place.
+    pub fn take_background(&mut self) -> Option<Background> {
+        self.background.size = None;
+        self.background.background.take()
+    }
+
+    pub fn fade_background(&mut self, background: Option<Background>) {
+        std::mem::swap(&mut self.background, &mut self.previous_background);
+        self.background.set(background);
+        self.background_fade = 0.0;
+    }
+
+    pub fn set_background_fade(&mut self, progress: f32) {
+        self.background_fade = progress.clamp(0.0, 1.0);
+        if self.background_fade == 1.0 {
+            self.previous_background.set(None);
+        }
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    /// Draws one frame, `bar_values` are normalized to 0.0..=1.0. Values past
+    /// `bars.amount` are ignored and missing bars aren't drawn. Does not swap buffers.
+    pub fn draw(&mut self, bar_values: &[f32], width: u32, height: u32) {
+        let fading = self.background_fade < 1.0;
+        let draw_background = self.background.background.is_some() || fading;
+        if draw_background {
+            self.background.upload(width, height);
+        }
+        if fading {
+            self.previous_background.upload(width, height);
+        }
+        let mut vertices: Vec<f32> = Vec::with_capacity(self.layout.bar_count as usize * 8);
+        self.layout.vertices(bar_values, &mut vertices);
+        let fwidth: f32 = width as f32;
+        let fheight: f32 = height as f32;
+        // Like the bars, the flash fades out, the background stays.
+        let flash = self.effects.flash.clamp(0.0, 1.0) * self.opacity;
+        let background_color = flash_background(self.background_color, flash);
+        unsafe {
+            gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (vertices.len() * std::mem::size_of::<f32>()) as GLsizeiptr,
+                vertices.as_ptr() as *const _,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::ClearColor(
+                background_color[0],
+                background_color[1],
+                background_color[2],
+                background_color[3],
+            );
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            if draw_background {
+                gl::UseProgram(self.background_program);
+                gl::BindVertexArray(self.background_vao);
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, self.previous_background.texture);
+                gl::ActiveTexture(gl::TEXTURE0);
+                gl::BindTexture(gl::TEXTURE_2D, self.background.texture);
+                gl::Uniform1f(self.background_flash_location, flash);
+                gl::Uniform1f(self.background_fade_location, self.background_fade);
+                gl::Uniform4fv(
+                    self.background_color_location,
+                    1,
+                    self.background_color.as_ptr(),
+                );
+                gl::Disable(gl::BLEND);
+                gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+                gl::Enable(gl::BLEND);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+                gl::BindVertexArray(self.vao);
+            }
+            gl::UseProgram(self.shader_program);
+            gl::Uniform2f(self.windows_size_location, fwidth, fheight);
+            gl::Uniform1f(
+                self.gradient_shift_location,
+                self.effects.gradient_shift.max(0.0),
+            );
+            gl::Uniform1f(self.glow_location, self.effects.glow.clamp(0.0, 1.0));
+            gl::Uniform1f(self.opacity_location, self.opacity);
+            gl::DrawElements(
+                gl::TRIANGLES,
+                // Only the bars uploaded above, the index buffer covers all of them.
+                (vertices.len() / 8 * 6) as GLsizei,
+                gl::UNSIGNED_SHORT,
+                ptr::null(),
+            );
+            gl::BindVertexArray(0);
+        }
+    }
+}
+
+// A background and the texture it is composed into.
+struct BackgroundTexture {
+    background: Option<Background>,
+    texture: u32,
+    // Size the texture was composed for.
+    size: Option<(u32, u32)>,
+}
+
+impl BackgroundTexture {
+    fn new() -> Self {
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            // Composed at the output size, so texels map to pixels one to one.
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        BackgroundTexture {
+            background: None,
+            texture,
+            size: None,
+        }
+    }
+
+    fn set(&mut self, background: Option<Background>) {
+        self.background = background;
+        self.size = None;
+    }
+
+    // Without a background a single transparent texel covers any size.
+    fn upload(&mut self, width: u32, height: u32) {
+        if self.size == Some((width, height)) {
+            return;
+        }
+        let (pixels, texture_width, texture_height) = match &self.background {
+            Some(background) => (background.compose(width, height).into_raw(), width, height),
+            None => (vec![0; 4], 1, 1),
+        };
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as i32,
+                texture_width as GLsizei,
+                texture_height as GLsizei,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const ffi::c_void,
+            );
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        self.size = Some((width, height));
+    }
+}
+
+impl Drop for BackgroundTexture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.texture) };
+    }
+}
+
+impl Drop for GlRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteBuffers(1, &self.ebo);
+            if self.gradient_colors_ssbo != 0 {
+                gl::DeleteBuffers(1, &self.gradient_colors_ssbo);
+            }
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.shader_program);
+            gl::DeleteVertexArrays(1, &self.background_vao);
+            gl::DeleteProgram(self.background_program);
+        }
+    }
+}
+
+/// Mixes the background towards opaque white by `flash`.
+pub fn flash_background(background_color: [f32; 4], flash: f32) -> [f32; 4] {
+    let flash = flash.clamp(0.0, 1.0);
+    background_color.map(|channel| channel + (1.0 - channel) * flash)
+}
+
+fn upload_gradient_uniforms(shader_program: u32, gradient_colors: &[[f32; 4]]) {
+    let size_name = CString::new("gradient_colors_size").unwrap();
+    let colors_name = CString::new("gradient_colors").unwrap();
+    unsafe {
+        gl::UseProgram(shader_program);
+        let size_location = gl::GetUniformLocation(shader_program, size_name.as_ptr());
+        let colors_location = gl::GetUniformLocation(shader_program, colors_name.as_ptr());
+        gl::Uniform1i(size_location, gradient_colors.len() as i32);
+        gl::Uniform4fv(
+            colors_location,
+            gradient_colors.len() as GLsizei,
+            gradient_colors.as_ptr() as *const f32,
+        );
+        gl::UseProgram(0);
+    }
+}
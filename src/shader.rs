@@ -0,0 +1,220 @@
+use gl::types::{GLenum, GLint, GLuint};
+use std::ffi::CString;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+impl ShaderStage {
+    fn gl_enum(self) -> GLenum {
+        match self {
+            ShaderStage::Vertex => gl::VERTEX_SHADER,
+            ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+        }
+    }
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex"),
+            ShaderStage::Fragment => write!(f, "fragment"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    InvalidSource(ShaderStage),
+    Compile { stage: ShaderStage, log: String },
+    Link { log: String },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::InvalidSource(stage) => {
+                write!(f, "{} shader source contains a nul byte", stage)
+            }
+            ShaderError::Compile { stage, log } => {
+                write!(f, "Failed to compile {} shader:\n{}", stage, log)
+            }
+            ShaderError::Link { log } => write!(f, "Failed to link shader program:\n{}", log),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// Compiles both stages and links them into a program. Shader objects are
+/// deleted in every case, so only the returned program has to be cleaned up.
+pub fn create_program(vertex_src: &str, fragment_src: &str) -> Result<GLuint, ShaderError> {
+    let vert_shader = compile_shader(ShaderStage::Vertex, vertex_src)?;
+    let frag_shader = match compile_shader(ShaderStage::Fragment, fragment_src) {
+        Ok(shader) => shader,
+        Err(error) => {
+            unsafe { gl::DeleteShader(vert_shader) };
+            return Err(error);
+        }
+    };
+    unsafe {
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert_shader);
+        gl::AttachShader(program, frag_shader);
+        gl::LinkProgram(program);
+        gl::DetachShader(program, vert_shader);
+        gl::DetachShader(program, frag_shader);
+        gl::DeleteShader(vert_shader);
+        gl::DeleteShader(frag_shader);
+        let mut status = gl::FALSE as GLint;
+        gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+        if status != gl::TRUE as GLint {
+            let log = program_info_log(program);
+            gl::DeleteProgram(program);
+            return Err(ShaderError::Link { log });
+        }
+        Ok(program)
+    }
+}
+
+pub fn compile_shader(stage: ShaderStage, source: &str) -> Result<GLuint, ShaderError> {
+    let c_source = CString::new(source).map_err(|_| ShaderError::InvalidSource(stage))?;
+    unsafe {
+        let shader = gl::CreateShader(stage.gl_enum());
+        gl::ShaderSource(shader, 1, &c_source.as_ptr(), std::ptr::null());
+        gl::CompileShader(shader);
+        let mut status = gl::FALSE as GLint;
+        gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+        if status != gl::TRUE as GLint {
+            let log = shader_info_log(shader);
+            gl::DeleteShader(shader);
+            return Err(ShaderError::Compile {
+                stage,
+                log: annotate_log(&log, source),
+            });
+        }
+        Ok(shader)
+    }
+}
+
+unsafe fn shader_info_log(shader: GLuint) -> String {
+    let mut log_size: GLint = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut log_size);
+    let mut log: Vec<u8> = vec![0; log_size.max(1) as usize];
+    gl::GetShaderInfoLog(shader, log_size, &mut log_size, log.as_mut_ptr() as *mut _);
+    log.truncate(log_size.max(0) as usize);
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+unsafe fn program_info_log(program: GLuint) -> String {
+    let mut log_size: GLint = 0;
+    gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut log_size);
+    let mut log: Vec<u8> = vec![0; log_size.max(1) as usize];
+    gl::GetProgramInfoLog(program, log_size, &mut log_size, log.as_mut_ptr() as *mut _);
+    log.truncate(log_size.max(0) as usize);
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+// Appends the offending source line under every log line that references one.
+fn annotate_log(log: &str, source: &str) -> String {
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut annotated = String::new();
+    for line in log.lines() {
+        annotated.push_str(line);
+        annotated.push('\n');
+        if let Some(line_number) = log_line_number(line) {
+            if let Some(source_line) = line_number
+                .checked_sub(1)
+                .and_then(|index| source_lines.get(index))
+            {
+                annotated.push_str(&format!(
+                    "    {} | {}\n",
+                    line_number,
+                    source_line.trim_end()
+                ));
+            }
+        }
+    }
+    annotated
+}
+
+// Drivers format locations differently: Mesa uses "0:12(5): error", NVIDIA uses
+// "0(12) : error" and some others use "ERROR: 0:12: ...".
+fn log_line_number(line: &str) -> Option<usize> {
+    let line = line.trim_start();
+    let line = line
+        .strip_prefix("ERROR: ")
+        .or_else(|| line.strip_prefix("WARNING: "))
+        .unwrap_or(line);
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == line.len() {
+        return None;
+    }
+    let rest = rest.strip_prefix(':').or_else(|| rest.strip_prefix('('))?;
+    let digits_end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..digits_end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str =
+        "#version 330 core\nout vec4 color;\nvoid main() {\n    color = vec4(1.0)\n}\n";
+
+    #[test]
+    fn reads_mesa_locations() {
+        assert_eq!(
+            log_line_number("0:4(23): error: syntax error, unexpected '}'"),
+            Some(4)
+        );
+        assert_eq!(log_line_number("  0:12(5): warning: unused"), Some(12));
+    }
+
+    #[test]
+    fn reads_nvidia_locations() {
+        assert_eq!(
+            log_line_number("0(4) : error C0000: syntax error, unexpected '}'"),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn reads_prefixed_locations() {
+        assert_eq!(log_line_number("ERROR: 0:4: '}' : syntax error"), Some(4));
+        assert_eq!(log_line_number("WARNING: 0:17: unused"), Some(17));
+    }
+
+    #[test]
+    fn ignores_lines_without_a_location() {
+        for line in [
+            "",
+            "error: linking failed",
+            "ERROR: 1 compilation errors.  No code generated.",
+            "0: no line",
+            "0:(5): missing line",
+        ] {
+            assert_eq!(log_line_number(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn annotates_lines_with_their_source() {
+        let log = "0:4(23): error: syntax error, unexpected '}'\n\
+                   0:99(1): error: past the end\n\
+                   error: 2 errors\n";
+        assert_eq!(
+            annotate_log(log, SOURCE),
+            "0:4(23): error: syntax error, unexpected '}'\n\
+             \x20   4 |     color = vec4(1.0)\n\
+             0:99(1): error: past the end\n\
+             error: 2 errors\n"
+        );
+        assert_eq!(annotate_log("", SOURCE), "");
+    }
+}
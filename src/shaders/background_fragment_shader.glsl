@@ -0,0 +1,22 @@
+in vec2 texture_position;
+out vec4 fragColor;
+uniform sampler2D background;
+uniform sampler2D previous_background;
+uniform vec4 background_color;
+uniform float fade;
+uniform float flash;
+// Like blend in software.rs.
+vec4 over_background(vec4 color) {
+    return vec4(mix(background_color.rgb, color.rgb, color.a), mix(background_color.a, color.a, color.a));
+}
+void main() {
+    vec4 color = mix(
+        over_background(texture(previous_background, texture_position)),
+        over_background(texture(background, texture_position)),
+        fade
+    );
+    // Like flash_background in renderer.rs.
+    color += (1.0 - color) * flash;
+    // Drawn without blending, like the cleared buffer it replaces.
+    fragColor = color;
+}
@@ -0,0 +1,8 @@
+out vec2 texture_position;
+void main() {
+    // A triangle strip over the whole viewport, no vertex buffer needed.
+    vec2 corner = vec2(float(gl_VertexID & 1), float(gl_VertexID >> 1));
+    // The texture holds its rows top to bottom.
+    texture_position = vec2(corner.x, 1.0 - corner.y);
+    gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+}
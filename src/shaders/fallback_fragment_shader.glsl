@@ -0,0 +1,20 @@
+#define MAX_GRADIENT_COLORS 32
+uniform int gradient_colors_size;
+uniform vec4 gradient_colors[MAX_GRADIENT_COLORS];
+uniform vec2 WindowSize;
+uniform float gradient_shift;
+uniform float glow;
+uniform float opacity;
+out vec4 fragColor;
+void main() {
+    if (gradient_colors_size == 1) {
+        fragColor = gradient_colors[0];
+    } else {
+        float findex = min(gl_FragCoord.y / WindowSize.y + gradient_shift, 1.0) * float(gradient_colors_size - 1);
+        int index = min(int(findex), gradient_colors_size - 2);
+        float step = findex - float(index);
+        fragColor = mix(gradient_colors[index], gradient_colors[index + 1], step);
+    }
+    fragColor.rgb = mix(fragColor.rgb, vec3(1.0), glow);
+    fragColor.a *= opacity;
+}
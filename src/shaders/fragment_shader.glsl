@@ -4,17 +4,19 @@ layout(std430, binding = 0) buffer GradientColors {
     vec4 gradient_colors[];
 };
 uniform vec2 WindowSize;
+uniform float gradient_shift;
+uniform float glow;
+uniform float opacity;
 out vec4 fragColor;
 void main() {
     if (gradient_colors_size == 1) {
         fragColor = gradient_colors[0];
     } else {
-        float findex = (gl_FragCoord.y * float(gradient_colors_size - 1)) / WindowSize.y;
-        int index = int(findex);
+        float findex = min(gl_FragCoord.y / WindowSize.y + gradient_shift, 1.0) * float(gradient_colors_size - 1);
+        int index = min(int(findex), gradient_colors_size - 2);
         float step = findex - float(index);
-        if (index == gradient_colors_size - 1) {
-            index--;
-        }
         fragColor = mix(gradient_colors[index], gradient_colors[index + 1], step);
     }
+    fragColor.rgb = mix(fragColor.rgb, vec3(1.0), glow);
+    fragColor.a *= opacity;
 }
@@ -0,0 +1,182 @@
+use crate::app_config::SilenceConfig;
+use std::time::{Duration, Instant};
+
+/// Fades the bars out once every bar stayed below `silence.threshold`
+/// for `silence.timeout` seconds, and back in on the first louder frame.
+pub struct Silence {
+    enabled: bool,
+    threshold: f32,
+    timeout: Duration,
+    fade_in: Duration,
+    fade_out: Duration,
+    silent_since: Option<Instant>,
+    // The current fade runs from `fade_from` to `visible` starting at `fade_start`.
+    visible: bool,
+    fade_from: f32,
+    fade_start: Instant,
+}
+
+impl Silence {
+    pub fn new(config: &SilenceConfig) -> Self {
+        Silence {
+            enabled: config.enabled.unwrap_or(true),
+            threshold: config.threshold.unwrap_or(0.02),
+            timeout: Duration::from_secs_f32(config.timeout.unwrap_or(5.0).max(0.0)),
+            fade_in: Duration::from_secs_f32(config.fade_in.unwrap_or(0.2).max(0.0)),
+            fade_out: Duration::from_secs_f32(config.fade_out.unwrap_or(1.0).max(0.0)),
+            silent_since: None,
+            visible: true,
+            fade_from: 1.0,
+            fade_start: Instant::now(),
+        }
+    }
+
+    /// Feeds the frame that arrived at `now`.
+    pub fn process(&mut self, bar_values: &[f32], now: Instant) {
+        if !self.enabled {
+            return;
+        }
+        if bar_values.iter().any(|value| *value >= self.threshold) {
+            self.silent_since = None;
+            if !self.visible {
+                self.fade_to(true, now);
+            }
+            return;
+        }
+        let silent_since = *self.silent_since.get_or_insert(now);
+        if self.visible && now.saturating_duration_since(silent_since) >= self.timeout {
+            self.fade_to(false, now);
+        }
+    }
+
+    fn fade_to(&mut self, visible: bool, now: Instant) {
+        self.fade_from = self.opacity(now);
+        self.fade_start = now;
+        self.visible = visible;
+    }
+
+    /// 1.0 while playing, 0.0 once faded out.
+    pub fn opacity(&self, now: Instant) -> f32 {
+        let (target, duration) = if self.visible {
+            (1.0, self.fade_in)
+        } else {
+            (0.0, self.fade_out)
+        };
+        let elapsed = now.saturating_duration_since(self.fade_start);
+        if elapsed >= duration {
+            return target;
+        }
+        let progress = elapsed.as_secs_f32() / duration.as_secs_f32();
+        self.fade_from + (target - self.fade_from) * progress
+    }
+
+    pub fn is_fading(&self, now: Instant) -> bool {
+        let target = if self.visible { 1.0 } else { 0.0 };
+        self.opacity(now) != target
+    }
+
+    /// Faded out completely, nothing has to be drawn until the music is back.
+    pub fn is_asleep(&self, now: Instant) -> bool {
+        !self.visible && !self.is_fading(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUIET: [f32; 2] = [0.0, 0.01];
+    const LOUD: [f32; 2] = [0.0, 0.5];
+
+    fn silence(fade_out: f32) -> Silence {
+        Silence::new(&SilenceConfig {
+            timeout: Some(2.0),
+            fade_in: Some(0.2),
+            fade_out: Some(fade_out),
+            ..Default::default()
+        })
+    }
+
+    fn millis(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    // Quiet frames every 100ms from `from` up to and including `to`.
+    fn feed_quiet(silence: &mut Silence, start: Instant, from: u64, to: u64) {
+        for time in (from..=to).step_by(100) {
+            silence.process(&QUIET, millis(start, time));
+        }
+    }
+
+    #[test]
+    fn short_silence_does_not_fade() {
+        let start = Instant::now();
+        let mut silence = silence(1.0);
+        feed_quiet(&mut silence, start, 0, 1900);
+        silence.process(&LOUD, millis(start, 2000));
+        feed_quiet(&mut silence, start, 2100, 3900);
+        assert_eq!(silence.opacity(millis(start, 3900)), 1.0);
+        assert!(!silence.is_fading(millis(start, 3900)));
+        assert!(!silence.is_asleep(millis(start, 3900)));
+    }
+
+    #[test]
+    fn fades_out_and_sleeps_after_the_timeout() {
+        let start = Instant::now();
+        let mut silence = silence(1.0);
+        feed_quiet(&mut silence, start, 0, 2000);
+        assert!(silence.is_fading(millis(start, 2000)));
+        assert!((silence.opacity(millis(start, 2500)) - 0.5).abs() < 1e-3);
+        assert!(!silence.is_asleep(millis(start, 2500)));
+        assert_eq!(silence.opacity(millis(start, 3000)), 0.0);
+        assert!(silence.is_asleep(millis(start, 3000)));
+        // More quiet frames change nothing.
+        feed_quiet(&mut silence, start, 3100, 5000);
+        assert!(silence.is_asleep(millis(start, 5000)));
+    }
+
+    #[test]
+    fn loud_frame_reverses_the_fade() {
+        let start = Instant::now();
+        let mut silence = silence(1.0);
+        feed_quiet(&mut silence, start, 0, 2000);
+        silence.process(&LOUD, millis(start, 2250));
+        // Back from 0.75 to 1.0 over the 200ms fade in.
+        assert!((silence.opacity(millis(start, 2250)) - 0.75).abs() < 1e-3);
+        assert!((silence.opacity(millis(start, 2350)) - 0.875).abs() < 1e-3);
+        assert_eq!(silence.opacity(millis(start, 2450)), 1.0);
+        assert!(!silence.is_fading(millis(start, 2450)));
+
+        // Waking up from sleep works the same.
+        feed_quiet(&mut silence, start, 2500, 5500);
+        assert!(silence.is_asleep(millis(start, 5500)));
+        silence.process(&LOUD, millis(start, 6000));
+        assert!(!silence.is_asleep(millis(start, 6000)));
+        assert!((silence.opacity(millis(start, 6100)) - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn disabled_never_sleeps() {
+        let start = Instant::now();
+        let mut silence = Silence::new(&SilenceConfig {
+            enabled: Some(false),
+            timeout: Some(1.0),
+            ..Default::default()
+        });
+        feed_quiet(&mut silence, start, 0, 10000);
+        assert_eq!(silence.opacity(millis(start, 10000)), 1.0);
+        assert!(!silence.is_asleep(millis(start, 10000)));
+    }
+
+    #[test]
+    fn no_fade_out_sleeps_at_once() {
+        let start = Instant::now();
+        let mut silence = silence(0.0);
+        feed_quiet(&mut silence, start, 0, 1900);
+        assert_eq!(silence.opacity(millis(start, 1900)), 1.0);
+        silence.process(&QUIET, millis(start, 2000));
+        assert_eq!(silence.opacity(millis(start, 2000)), 0.0);
+        assert!(!silence.is_fading(millis(start, 2000)));
+        assert!(silence.is_asleep(millis(start, 2000)));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::app_config::{expand_home, BackgroundConfig, SlideshowOrder};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_INTERVAL: f32 = 300.0;
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "webp"];
+
+/// Picks the image `background.directory` shows and when it changes.
+pub struct Slideshow {
+    directory: PathBuf,
+    order: SlideshowOrder,
+    interval: Duration,
+    images: Vec<PathBuf>,
+    position: usize,
+    next_change: Instant,
+    random_state: u64,
+}
+
+impl Slideshow {
+    /// None without `background.directory`, fails if it holds no image.
+    pub fn new(config: &BackgroundConfig, now: Instant) -> Result<Option<Self>, String> {
+        let Some(directory) = &config.directory else {
+            return Ok(None);
+        };
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        let interval = config.interval.unwrap_or(DEFAULT_INTERVAL).max(1.0);
+        let mut slideshow = Slideshow {
+            directory: expand_home(directory),
+            order: config.order,
+            interval: Duration::from_secs_f32(interval),
+            images: Vec::new(),
+            position: 0,
+            next_change: now + Duration::from_secs_f32(interval),
+            // xorshift gets stuck at 0.
+            random_state: seed | 1,
+        };
+        slideshow.images = slideshow.list_images()?;
+        if slideshow.images.is_empty() {
+            return Err(format!("No images in {}", directory));
+        }
+        slideshow.shuffle();
+        Ok(Some(slideshow))
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.images[self.position]
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.next_change
+    }
+
+    /// Moves to the next image and shows it for a whole interval. The directory
+    /// is read again after the last one, picking up added and removed images.
+    pub fn advance(&mut self, now: Instant) -> &Path {
+        self.next_change = now + self.interval;
+        self.position += 1;
+        if self.position == self.images.len() {
+            let last = self.images[self.images.len() - 1].clone();
+            // Keeps showing the old images if none are left.
+            match self.list_images() {
+                Ok(images) if !images.is_empty() => self.images = images,
+                Ok(_) => eprintln!("No images left in {}", self.directory.display()),
+                Err(error) => eprintln!("{}", error),
+            }
+            self.position = 0;
+            self.shuffle();
+            // Never the same image twice in a row, unless it's the only one.
+            if self.images.len() > 1 && self.images[0] == last {
+                self.images.swap(0, 1);
+            }
+        }
+        self.current()
+    }
+
+    fn list_images(&self) -> Result<Vec<PathBuf>, String> {
+        let entries = fs::read_dir(&self.directory)
+            .map_err(|error| format!("Unable to read {}: {}", self.directory.display(), error))?;
+        let mut images: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        IMAGE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+                    })
+            })
+            .collect();
+        images.sort();
+        Ok(images)
+    }
+
+    // Fisher-Yates with xorshift64, good enough to mix up wallpapers.
+    fn shuffle(&mut self) {
+        if self.order != SlideshowOrder::Shuffle {
+            return;
+        }
+        for index in (1..self.images.len()).rev() {
+            self.random_state ^= self.random_state << 13;
+            self.random_state ^= self.random_state >> 7;
+            self.random_state ^= self.random_state << 17;
+            let other = (self.random_state % (index as u64 + 1)) as usize;
+            self.images.swap(index, other);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory_with(name: &str, files: &[&str]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "wallpaper-cava-slideshow-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for file in files {
+            fs::write(directory.join(file), []).unwrap();
+        }
+        directory
+    }
+
+    fn config(directory: &Path, order: SlideshowOrder) -> BackgroundConfig {
+        BackgroundConfig {
+            directory: Some(directory.to_str().unwrap().to_string()),
+            interval: Some(10.0),
+            order,
+            ..Default::default()
+        }
+    }
+
+    fn file_name(path: &Path) -> &str {
+        path.file_name().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn goes_through_images_by_name_and_picks_up_new_ones() {
+        let directory = directory_with("sequential", &["b.png", "a.JPG", "notes.txt"]);
+        let now = Instant::now();
+        let mut slideshow = Slideshow::new(&config(&directory, SlideshowOrder::Sequential), now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(file_name(slideshow.current()), "a.JPG");
+        assert!(!slideshow.is_due(now + Duration::from_secs(9)));
+        assert!(slideshow.is_due(now + Duration::from_secs(10)));
+        assert_eq!(file_name(slideshow.advance(now)), "b.png");
+        fs::write(directory.join("c.webp"), []).unwrap();
+        assert_eq!(file_name(slideshow.advance(now)), "a.JPG");
+        assert_eq!(file_name(slideshow.advance(now)), "b.png");
+        assert_eq!(file_name(slideshow.advance(now)), "c.webp");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn shuffle_shows_every_image_once_per_round() {
+        let files = ["1.png", "2.png", "3.png", "4.png", "5.png"];
+        let directory = directory_with("shuffle", &files);
+        let now = Instant::now();
+        let mut slideshow = Slideshow::new(&config(&directory, SlideshowOrder::Shuffle), now)
+            .unwrap()
+            .unwrap();
+        let mut previous = slideshow.current().to_path_buf();
+        let mut round = vec![previous.clone()];
+        for _ in 0..files.len() * 3 {
+            let image = slideshow.advance(now).to_path_buf();
+            assert_ne!(image, previous);
+            round.push(image.clone());
+            if round.len() == files.len() {
+                round.sort();
+                round.dedup();
+                assert_eq!(round.len(), files.len());
+                round.clear();
+            }
+            previous = image;
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn fails_without_images() {
+        let directory = directory_with("empty", &["notes.txt"]);
+        assert!(Slideshow::new(
+            &config(&directory, SlideshowOrder::Sequential),
+            Instant::now()
+        )
+        .is_err());
+        assert!(Slideshow::new(&BackgroundConfig::default(), Instant::now())
+            .unwrap()
+            .is_none());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,499 @@
+use crate::app_config::Config;
+use crate::appearance::Appearance;
+use crate::background::Background;
+use crate::beat::EffectLevels;
+use crate::geometry::BarLayout;
+use crate::renderer::{flash_background, Renderer};
+use image::RgbaImage;
+use smithay_client_toolkit::shm::slot::{Buffer, SlotPool};
+use smithay_client_toolkit::shm::Shm;
+use wayland_client::protocol::{wl_shm, wl_surface::WlSurface};
+
+/// CPU rasterizer drawing into `wl_shm` buffers, for machines without a usable
+/// EGL/GL stack. Output matches the GL path up to rounding.
+pub struct SoftwareRenderer {
+    pool: SlotPool,
+    buffer: Option<Buffer>,
+    rasterizer: Rasterizer,
+}
+
+/// Draws bars into a plain ARGB8888 pixel buffer.
+pub struct Rasterizer {
+    width: u32,
+    height: u32,
+    layout: BarLayout,
+    gradient_colors: Vec<[f32; 4]>,
+    background_color: [f32; 4],
+    effects: EffectLevels,
+    opacity: f32,
+    background: Option<Background>,
+    previous_background: Option<Background>,
+    background_fade: f32,
+    // The backgrounds composed at the current size.
+    background_layer: Option<RgbaImage>,
+    previous_background_layer: Option<RgbaImage>,
+    // The same blended over blended_color, B, G, R, A like the canvas. Kept until
+    // the layers or the color change, fade and flash are applied while
+    // copying them into the canvas.
+    background_pixels: Vec<u8>,
+    previous_background_pixels: Vec<u8>,
+    blended_color: Option<[f32; 4]>,
+}
+
+impl SoftwareRenderer {
+    pub fn new(shm: &Shm, config: &Config) -> Self {
+        let pool = SlotPool::new(256 * 256 * 4, shm).expect("Failed to create wl_shm pool");
+        SoftwareRenderer {
+            pool,
+            buffer: None,
+            rasterizer: Rasterizer::new(config, 256, 256),
+        }
+    }
+}
+
+impl Rasterizer {
+    pub fn new(config: &Config, width: u32, height: u32) -> Self {
+        let appearance = Appearance::from_config(config);
+        Rasterizer {
+            width,
+            height,
+            layout: BarLayout::new(config.bars.amount, config.bars.gap),
+            gradient_colors: appearance.gradient_colors,
+            background_color: appearance.background_color,
+            effects: EffectLevels::default(),
+            opacity: 1.0,
+            background: None,
+            previous_background: None,
+            background_fade: 1.0,
+            background_layer: None,
+            previous_background_layer: None,
+            background_pixels: Vec::new(),
+            previous_background_pixels: Vec::new(),
+            blended_color: None,
+        }
+    }
+
+    pub fn set_background(&mut self, background: Option<Background>) {
+        self.background = background;
+        self.previous_background = None;
+        self.background_fade = 1.0;
+        self.compose_background();
+    }
+
+    pub fn fade_background(&mut self, background: Option<Background>) {
+        // The current layer is already composed at this size.
+        self.previous_background = std::mem::replace(&mut self.background, background);
+        self.previous_background_layer = self.background_layer.take();
+        self.background_layer = self
+            .background
+            .as_ref()
+            .map(|background| background.compose(self.width, self.height));
+        self.background_fade = 0.0;
+        self.blended_color = None;
+    }
+
+    pub fn set_background_fade(&mut self, progress: f32) {
+        self.background_fade = progress.clamp(0.0, 1.0);
+        if self.background_fade == 1.0 && self.previous_background.is_some() {
+            self.previous_background = None;
+            self.previous_background_layer = None;
+            self.blended_color = None;
+        }
+    }
+
+    fn compose_background(&mut self) {
+        let (width, height) = (self.width, self.height);
+        self.background_layer = self
+            .background
+            .as_ref()
+            .map(|background| background.compose(width, height));
+        self.previous_background_layer = self
+            .previous_background
+            .as_ref()
+            .map(|background| background.compose(width, height));
+        self.blended_color = None;
+    }
+
+    fn has_background_layer(&self) -> bool {
+        self.background_layer.is_some() || self.previous_background_layer.is_some()
+    }
+
+    pub fn set_appearance(&mut self, appearance: &Appearance) {
+        self.gradient_colors = appearance.gradient_colors.clone();
+        self.background_color = appearance.background_color;
+        self.layout.bar_gap = appearance.bar_gap;
+    }
+
+    pub fn set_effects(&mut self, effects: EffectLevels) {
+        self.effects = effects;
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.compose_background();
+    }
+
+    /// Replicates the fragment shader and glBlendFunc(SRC_ALPHA, ONE_MINUS_SRC_ALPHA),
+    /// writing little-endian ARGB8888 (B, G, R, A in memory) rows top to bottom.
+    pub fn rasterize(&mut self, canvas: &mut [u8], bar_values: &[f32]) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        // Like the bars, the flash fades out, the background stays.
+        let flash = self.effects.flash * self.opacity;
+        let background_color = flash_background(self.background_color, flash);
+        let has_background_layer = self.has_background_layer();
+        if has_background_layer {
+            self.draw_background_layers(canvas, flash);
+        } else {
+            let background = to_bgra(background_color);
+            for pixel in canvas.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&background);
+            }
+        }
+        if self.gradient_colors.is_empty() {
+            return;
+        }
+
+        // Bars never overlap, so every covered pixel of a row gets the same color,
+        // unless they are blended over an image.
+        let row_colors: Vec<[f32; 4]> = (0..height)
+            .map(|row_from_bottom| self.gradient_color(row_from_bottom as f32 + 0.5))
+            .collect();
+        let flat_row_colors: Vec<[u8; 4]> = row_colors
+            .iter()
+            .map(|color| to_bgra(blend(*color, background_color)))
+            .collect();
+
+        for (i, bar_value) in bar_values
+            .iter()
+            .enumerate()
+            .take(self.layout.bar_count as usize)
+        {
+            let (left, right) = self.layout.bar_edges(i);
+            // A pixel is covered when its center is inside the bar, like GL rasterization.
+            let first_column = ndc_to_pixel(left, width);
+            let last_column = ndc_to_pixel(right, width);
+            let rows = ndc_to_pixel(BarLayout::bar_top(*bar_value), height);
+            for row_from_bottom in 0..rows.min(height) {
+                let row_start = (height - 1 - row_from_bottom) * width * 4;
+                for column in first_column..last_column {
+                    let pixel = &mut canvas[row_start + column * 4..][..4];
+                    if has_background_layer {
+                        let color = blend(row_colors[row_from_bottom], from_bgra(pixel));
+                        pixel.copy_from_slice(&to_bgra(color));
+                    } else {
+                        pixel.copy_from_slice(&flat_row_colors[row_from_bottom]);
+                    }
+                }
+            }
+        }
+    }
+
+    // Like the background shader: both layers blended over the background color,
+    // then mixed and flashed.
+    fn draw_background_layers(&mut self, canvas: &mut [u8], flash: f32) {
+        let fading = self.background_fade < 1.0;
+        if self.blended_color != Some(self.background_color) {
+            let color = to_bgra(self.background_color);
+            let size = canvas.len();
+            blend_layer(
+                &self.background_layer,
+                color,
+                size,
+                &mut self.background_pixels,
+            );
+            if fading {
+                blend_layer(
+                    &self.previous_background_layer,
+                    color,
+                    size,
+                    &mut self.previous_background_pixels,
+                );
+            }
+            self.blended_color = Some(self.background_color);
+        }
+        let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u32;
+        let fade = to_byte(self.background_fade);
+        let flash = to_byte(flash);
+        if !fading && flash == 0 {
+            canvas.copy_from_slice(&self.background_pixels);
+            return;
+        }
+        let previous = if fading {
+            &self.previous_background_pixels
+        } else {
+            &self.background_pixels
+        };
+        for ((pixel, current), previous) in canvas
+            .chunks_exact_mut(4)
+            .zip(self.background_pixels.chunks_exact(4))
+            .zip(previous.chunks_exact(4))
+        {
+            for channel in 0..4 {
+                let value = mix_bytes(previous[channel], current[channel], fade);
+                pixel[channel] = mix_bytes(value, 255, flash);
+            }
+        }
+    }
+
+    fn gradient_color(&self, frag_y: f32) -> [f32; 4] {
+        let gradient_colors_size = self.gradient_colors.len();
+        let mut color = if gradient_colors_size == 1 {
+            self.gradient_colors[0]
+        } else {
+            let findex = (frag_y / self.height as f32 + self.effects.gradient_shift.max(0.0))
+                .min(1.0)
+                * (gradient_colors_size - 1) as f32;
+            let index = (findex as usize).min(gradient_colors_size - 2);
+            let step = findex - index as f32;
+            let from = self.gradient_colors[index];
+            let to = self.gradient_colors[index + 1];
+            [
+                from[0] + (to[0] - from[0]) * step,
+                from[1] + (to[1] - from[1]) * step,
+                from[2] + (to[2] - from[2]) * step,
+                from[3] + (to[3] - from[3]) * step,
+            ]
+        };
+        let glow = self.effects.glow.clamp(0.0, 1.0);
+        for channel in &mut color[..3] {
+            *channel += (1.0 - *channel) * glow;
+        }
+        color[3] *= self.opacity;
+        color
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn configure(&mut self, _surface: &WlSurface, width: u32, height: u32) {
+        self.rasterizer.resize(width, height);
+        self.buffer = None;
+    }
+
+    fn set_effects(&mut self, effects: EffectLevels) {
+        self.rasterizer.set_effects(effects);
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        self.rasterizer.set_opacity(opacity);
+    }
+
+    fn reload(&mut self, config: &Config) {
+        let mut rasterizer = Rasterizer::new(config, self.rasterizer.width, self.rasterizer.height);
+        // Already composed at this size, it is blended over the new color on the next draw.
+        rasterizer.background = self.rasterizer.background.take();
+        rasterizer.background_layer = self.rasterizer.background_layer.take();
+        self.rasterizer = rasterizer;
+    }
+
+    fn set_background(&mut self, background: Option<Background>) {
+        self.rasterizer.set_background(background);
+    }
+
+    fn fade_background(&mut self, background: Option<Background>) {
+        self.rasterizer.fade_background(background);
+    }
+
+    fn set_background_fade(&mut self, progress: f32) {
+        self.rasterizer.set_background_fade(progress);
+    }
+
+    fn set_appearance(&mut self, appearance: &Appearance) {
+        self.rasterizer.set_appearance(appearance);
+    }
+
+    fn draw(&mut self, surface: &WlSurface, bar_values: &[f32]) {
+        let width = self.rasterizer.width as i32;
+        let height = self.rasterizer.height as i32;
+        let stride = width * 4;
+        // Reuse the last buffer unless the compositor still holds it.
+        let reusable = match self.buffer.as_ref() {
+            Some(buffer) => self.pool.canvas(buffer).is_some(),
+            None => false,
+        };
+        if !reusable {
+            let (buffer, _) = self
+                .pool
+                .create_buffer(width, height, stride, wl_shm::Format::Argb8888)
+                .expect("Failed to create wl_shm buffer");
+            self.buffer = Some(buffer);
+        }
+        let buffer = self.buffer.as_ref().unwrap();
+        let canvas = self.pool.canvas(buffer).unwrap();
+        self.rasterizer.rasterize(canvas, bar_values);
+        surface.damage_buffer(0, 0, width, height);
+        buffer
+            .attach_to(surface)
+            .expect("Failed to attach wl_shm buffer");
+        surface.commit();
+    }
+}
+
+fn ndc_to_pixel(ndc: f32, size: usize) -> usize {
+    let position = (ndc + 1.0) / 2.0 * size as f32;
+    ((position - 0.5).ceil().max(0.0) as usize).min(size)
+}
+
+// Fills `pixels` with `size` bytes, reusing them so that only a resize allocates.
+// A missing layer is transparent, like the texture the GL path uses.
+fn blend_layer(layer: &Option<RgbaImage>, color: [u8; 4], size: usize, pixels: &mut Vec<u8>) {
+    pixels.clear();
+    match layer {
+        Some(layer) => {
+            for texel in layer.pixels() {
+                let alpha = texel[3] as u32;
+                pixels.extend_from_slice(&[
+                    mix_bytes(color[0], texel[2], alpha),
+                    mix_bytes(color[1], texel[1], alpha),
+                    mix_bytes(color[2], texel[0], alpha),
+                    mix_bytes(color[3], texel[3], alpha),
+                ]);
+            }
+        }
+        None => {
+            for _ in 0..size / 4 {
+                pixels.extend_from_slice(&color);
+            }
+        }
+    }
+}
+
+// `progress` 0 is `from`, 255 is `to`.
+fn mix_bytes(from: u8, to: u8, progress: u32) -> u8 {
+    ((from as u32 * (255 - progress) + to as u32 * progress + 127) / 255) as u8
+}
+
+fn blend(source: [f32; 4], destination: [f32; 4]) -> [f32; 4] {
+    let alpha = source[3];
+    [
+        source[0] * alpha + destination[0] * (1.0 - alpha),
+        source[1] * alpha + destination[1] * (1.0 - alpha),
+        source[2] * alpha + destination[2] * (1.0 - alpha),
+        source[3] * alpha + destination[3] * (1.0 - alpha),
+    ]
+}
+
+fn from_bgra(pixel: &[u8]) -> [f32; 4] {
+    [pixel[2], pixel[1], pixel[0], pixel[3]].map(|channel| channel as f32 / 255.0)
+}
+
+fn to_bgra(color: [f32; 4]) -> [u8; 4] {
+    let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [
+        to_byte(color[2]),
+        to_byte(color[1]),
+        to_byte(color[0]),
+        to_byte(color[3]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    fn config(amount: u32, gap: f32, colors: &[&str]) -> Config {
+        let colors: String = colors
+            .iter()
+            .enumerate()
+            .map(|(index, color)| format!("color_{} = '{}'\n", index + 1, color))
+            .collect();
+        Config::parse(
+            &format!(
+                "[general]\nframerate = 60\nbackground_color = '#000000'\n\
+                 [bars]\namount = {}\ngap = {}\n[colors]\n{}[smoothing]\n",
+                amount, gap, colors
+            ),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn rasterize(rasterizer: &mut Rasterizer, bar_values: &[f32]) -> Vec<[u8; 4]> {
+        let mut canvas = vec![0; (rasterizer.width * rasterizer.height * 4) as usize];
+        rasterizer.rasterize(&mut canvas, bar_values);
+        canvas
+            .chunks_exact(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn places_bars_on_their_columns() {
+        // Bars and gaps one pixel wide each.
+        let mut rasterizer = Rasterizer::new(&config(4, 1.0, &["#ffffff"]), 7, 4);
+        let pixels = rasterize(&mut rasterizer, &[1.0, 0.5, 0.0, 0.25]);
+        let heights = [4, 0, 2, 0, 0, 0, 1];
+        for (index, pixel) in pixels.iter().enumerate() {
+            let (column, row_from_bottom) = (index % 7, 3 - index / 7);
+            let expected = if row_from_bottom < heights[column] {
+                WHITE
+            } else {
+                BLACK
+            };
+            assert_eq!(
+                *pixel, expected,
+                "column {} row {}",
+                column, row_from_bottom
+            );
+        }
+    }
+
+    #[test]
+    fn gradient_runs_bottom_to_top() {
+        let mut rasterizer = Rasterizer::new(&config(1, 0.0, &["#ff0000", "#0000ff"]), 1, 4);
+        let pixels = rasterize(&mut rasterizer, &[1.0]);
+        // Pixel centers, 7/8 and 1/8 of the way up.
+        assert_eq!(pixels[0], [223, 0, 32, 255]);
+        assert_eq!(pixels[3], [32, 0, 223, 255]);
+    }
+
+    #[test]
+    fn blends_the_background_layer() {
+        let config = config(1, 0.0, &["#ffffff"]);
+        let mut rasterizer = Rasterizer::new(&config, 1, 1);
+        let image = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 128]));
+        rasterizer.set_background(Background::from_image(&config.background, Some(image)));
+        // Half red over black.
+        assert_eq!(rasterize(&mut rasterizer, &[0.0]), [[0, 0, 128, 191]]);
+        // Halfway back to black.
+        rasterizer.fade_background(None);
+        rasterizer.set_background_fade(0.5);
+        assert_eq!(rasterize(&mut rasterizer, &[0.0]), [[0, 0, 64, 223]]);
+        // Halfway to white.
+        rasterizer.set_background_fade(0.0);
+        rasterizer.set_effects(EffectLevels {
+            flash: 0.5,
+            ..EffectLevels::default()
+        });
+        assert_eq!(rasterize(&mut rasterizer, &[0.0]), [[128, 128, 192, 223]]);
+        // Bars are drawn over it.
+        assert_eq!(rasterize(&mut rasterizer, &[1.0]), [WHITE]);
+    }
+
+    #[test]
+    fn fading_out_keeps_the_background() {
+        let config = config(2, 0.0, &["#ffffff"]);
+        let mut rasterizer = Rasterizer::new(&config, 2, 1);
+        let image = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 128]));
+        rasterizer.set_background(Background::from_image(&config.background, Some(image)));
+        let background = rasterize(&mut rasterizer, &[0.0, 0.0]);
+        rasterizer.set_effects(EffectLevels {
+            flash: 1.0,
+            ..EffectLevels::default()
+        });
+        rasterizer.set_opacity(0.0);
+        assert_eq!(rasterize(&mut rasterizer, &[1.0, 1.0]), background);
+        // Without an image the background color stays too.
+        rasterizer.set_background(None);
+        assert_eq!(rasterize(&mut rasterizer, &[1.0, 1.0]), [BLACK; 2]);
+    }
+}
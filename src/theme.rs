@@ -0,0 +1,297 @@
+use crate::app_config::{expand_home, is_hex_color, Config, ConfigColor, HexColorConfig};
+use crate::image_palette::ImagePalette;
+use indexmap::IndexMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Named colors from a pywal or wallust `colors.json`, a matugen JSON export or a
+/// base16 scheme. Nested JSON keys are joined with dots, e.g. `colors.color1` or
+/// `colors.dark.primary`, base16 colors keep their names like `base0D`.
+#[derive(Debug)]
+pub struct Palette {
+    colors: IndexMap<String, String>,
+}
+
+impl Palette {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(expand_home(path))
+            .map_err(|error| format!("Unable to read {}: {}", path, error))?;
+        Palette::parse(&source).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut colors = IndexMap::new();
+        if source.trim_start().starts_with('{') {
+            let value: serde_json::Value =
+                serde_json::from_str(source).map_err(|error| error.to_string())?;
+            collect_json_colors(&value, "", &mut colors);
+        } else {
+            collect_base16_colors(source, &mut colors);
+        }
+        if colors.is_empty() {
+            return Err("No colors found".to_string());
+        }
+        Ok(Palette { colors })
+    }
+
+    /// Looks a color up by its full name or the end of it after a dot, so
+    /// `color1` finds `colors.color1`. Names matching several colors are rejected.
+    pub fn get(&self, name: &str) -> Result<&str, String> {
+        let suffix = format!(".{}", name);
+        let mut matches = self
+            .colors
+            .iter()
+            .filter(|(key, _)| *key == name || key.ends_with(&suffix));
+        match (matches.next(), matches.next()) {
+            (Some((_, color)), None) => Ok(color),
+            (Some((first, _)), Some((second, _))) => Err(format!(
+                "{} matches both {} and {}, use the longer name",
+                name, first, second
+            )),
+            (None, _) => Err(format!("No color named {}", name)),
+        }
+    }
+}
+
+fn collect_json_colors(
+    value: &serde_json::Value,
+    path: &str,
+    colors: &mut IndexMap<String, String>,
+) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                collect_json_colors(value, &path, colors);
+            }
+        }
+        serde_json::Value::String(color) if is_hex_color(color) => {
+            colors.insert(path.to_string(), color.clone());
+        }
+        _ => {}
+    }
+}
+
+// base16 schemes are flat YAML, `base00: "181818" # comment`, newer ones nest
+// the colors under `palette:` and write them with a #.
+fn collect_base16_colors(source: &str, colors: &mut IndexMap<String, String>) {
+    for line in source.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value.split(" #").next().unwrap_or_default().trim(),
+        };
+        let color = if value.starts_with('#') {
+            value.to_string()
+        } else {
+            format!("#{}", value)
+        };
+        if is_hex_color(&color) {
+            colors.insert(key.trim().to_string(), color);
+        }
+    }
+}
+
+/// `colors.import.from_image` value deriving the colors from the image the background shows.
+pub const FROM_BACKGROUND: &str = "background";
+
+/// Replaces the gradient with the colors of `colors.import.file` named in
+/// `colors.import.gradient`, and the background color with the one named in
+/// `colors.import.background`, keeping its alpha. With `colors.import.from_image`
+/// both come from the image instead.
+pub fn apply_import(config: &mut Config) -> Result<(), String> {
+    let Some(import) = &config.colors.import else {
+        return Ok(());
+    };
+    if let Some(from_image) = &import.from_image {
+        if import.file.is_some() {
+            return Err(
+                "colors.import.file and colors.import.from_image can't be used together"
+                    .to_string(),
+            );
+        }
+        let background = &config.background;
+        if from_image == FROM_BACKGROUND
+            && background.image.is_none()
+            && background.directory.is_none()
+        {
+            return Err(
+                "colors.import.from_image = \"background\" needs background.image or background.directory"
+                    .to_string(),
+            );
+        }
+        if let Some(path) = from_image_path(config) {
+            apply_image_palette(config, &path)?;
+        }
+        return Ok(());
+    }
+    let Some(file) = &import.file else {
+        return Ok(());
+    };
+    let palette = Palette::load(file)?;
+    let gradient = import
+        .gradient
+        .as_ref()
+        .ok_or("colors.import.gradient is required with colors.import.file")?;
+    let gradient = gradient
+        .iter()
+        .map(|name| palette.get(name).map(str::to_string))
+        .collect::<Result<_, String>>()?;
+    let background = import.background.clone();
+    config.colors.gradient_colors = numbered_colors(gradient);
+    if let Some(name) = &background {
+        let hex = palette.get(name)?.to_string();
+        config.general.background_color = match &config.general.background_color {
+            ConfigColor::Simple(_) => ConfigColor::Simple(hex),
+            ConfigColor::Complex(color) => ConfigColor::Complex(HexColorConfig {
+                hex,
+                alpha: color.alpha,
+            }),
+        };
+    }
+    Ok(())
+}
+
+/// The image `colors.import.from_image` derives the colors from. None while a slideshow
+/// picks it, its images are passed to `apply_image_palette` as they are shown.
+pub fn from_image_path(config: &Config) -> Option<PathBuf> {
+    let from_image = config.colors.from_image()?;
+    if from_image != FROM_BACKGROUND {
+        return Some(expand_home(from_image));
+    }
+    if config.background.directory.is_some() {
+        return None;
+    }
+    config.background.image.as_deref().map(expand_home)
+}
+
+/// Sets the gradient and background color derived from `image`.
+pub fn apply_image_palette(config: &mut Config, image: &Path) -> Result<(), String> {
+    apply_palette(config, ImagePalette::load(image)?);
+    Ok(())
+}
+
+/// Sets the gradient and background color of `palette`.
+pub fn apply_palette(config: &mut Config, palette: ImagePalette) {
+    config.colors.gradient_colors = numbered_colors(palette.gradient);
+    config.general.background_color = ConfigColor::Complex(HexColorConfig {
+        hex: palette.background,
+        alpha: Some(palette.background_alpha),
+    });
+}
+
+/// Gradient colors named `color_1`, `color_2`... in order.
+pub fn numbered_colors(colors: Vec<String>) -> IndexMap<String, ConfigColor> {
+    colors
+        .into_iter()
+        .enumerate()
+        .map(|(index, hex)| (format!("color_{}", index + 1), ConfigColor::Simple(hex)))
+        .collect()
+}
+
+/// Notices when `colors.import.file` or `colors.import.from_image` was written,
+/// or pointed somewhere else if it is a symlink.
+pub struct ImportWatcher {
+    path: PathBuf,
+    modified: Modified,
+}
+
+type Modified = (Option<SystemTime>, Option<SystemTime>);
+
+impl ImportWatcher {
+    pub fn new(config: &Config) -> Option<Self> {
+        let file = config
+            .colors
+            .import
+            .as_ref()
+            .and_then(|import| import.file.as_ref());
+        let path = match file {
+            Some(file) => expand_home(file),
+            None => from_image_path(config)?,
+        };
+        let modified = modified(&path);
+        Some(ImportWatcher { path, modified })
+    }
+
+    /// Whether the file changed since the last call, or since `new`.
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        // Deleted, possibly just before being written again.
+        modified.0.is_some()
+    }
+}
+
+// Of the file and of the symlink to it.
+fn modified(path: &Path) -> Modified {
+    (
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok(),
+        fs::symlink_metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pywal_colors() {
+        let palette = Palette::parse(
+            r##"{
+                "wallpaper": "/home/user/wall.png",
+                "alpha": "100",
+                "special": { "background": "#1e1e2e", "foreground": "#cdd6f4" },
+                "colors": { "color1": "#f38ba8", "color10": "#a6e3a1" }
+            }"##,
+        )
+        .unwrap();
+        assert_eq!(palette.get("color1"), Ok("#f38ba8"));
+        assert_eq!(palette.get("color10"), Ok("#a6e3a1"));
+        assert_eq!(palette.get("background"), Ok("#1e1e2e"));
+        assert!(palette.get("wallpaper").is_err());
+    }
+
+    #[test]
+    fn rejects_ambiguous_matugen_names() {
+        let palette = Palette::parse(
+            r##"{ "colors": {
+                "dark": { "primary": "#d0bcff" },
+                "light": { "primary": "#6750a4" }
+            } }"##,
+        )
+        .unwrap();
+        assert!(palette.get("primary").is_err());
+        assert_eq!(palette.get("dark.primary"), Ok("#d0bcff"));
+        assert_eq!(palette.get("colors.light.primary"), Ok("#6750a4"));
+    }
+
+    #[test]
+    fn reads_base16_schemes() {
+        let palette = Palette::parse(
+            "scheme: \"Default Dark\"\nauthor: \"Chris Kempson\"\nbase00: \"181818\" # background\nbase0D: '7cafc2'\n",
+        )
+        .unwrap();
+        assert_eq!(palette.get("base00"), Ok("#181818"));
+        assert_eq!(palette.get("base0D"), Ok("#7cafc2"));
+        let palette = Palette::parse(
+            "system: \"base16\"\npalette:\n  base08: \"#ab4642\"\n  base09: \"#dc9656\"\n",
+        )
+        .unwrap();
+        assert_eq!(palette.get("base09"), Ok("#dc9656"));
+    }
+}
@@ -0,0 +1,120 @@
+#![cfg(feature = "dbus")]
+
+use calloop::EventLoop;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command as Process, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use wallpaper_cava::dbus::{DbusService, OBJECT_PATH, SERVICE_NAME};
+use wallpaper_cava::ipc::{Command, Reply};
+use zbus::blocking::connection::Builder;
+use zbus::blocking::proxy::Builder as ProxyBuilder;
+use zbus::blocking::Proxy;
+use zbus::proxy::CacheProperties;
+
+// Kills the private bus when the test ends, passing or not.
+struct Bus(Child);
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn start_bus() -> (Bus, String) {
+    let child = Process::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("dbus-daemon should be installed");
+    let mut bus = Bus(child);
+    let mut address = String::new();
+    BufReader::new(bus.0.stdout.take().unwrap())
+        .read_line(&mut address)
+        .unwrap();
+    (bus, address.trim().to_string())
+}
+
+#[test]
+#[ignore = "needs dbus-daemon, run with --ignored where it is installed"]
+fn serves_methods_properties_and_beats() {
+    let (_bus, address) = start_bus();
+    let mut event_loop: EventLoop<Vec<Command>> = EventLoop::try_new().unwrap();
+    let service = DbusService::start(
+        &event_loop.handle(),
+        Some(&address),
+        |command, commands: &mut Vec<Command>| {
+            commands.push(command.clone());
+            match command {
+                Command::Preset(name) => Reply::error(format!("No preset named {}", name)),
+                _ => Reply::ok(),
+            }
+        },
+    )
+    .unwrap();
+
+    let (ready_sender, ready_receiver) = mpsc::channel();
+    let client_address = address.clone();
+    let client = thread::spawn(move || {
+        let connection = Builder::address(client_address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        // Cached properties only change once the proxy saw PropertiesChanged, which can
+        // arrive after the Beat signal.
+        let proxy: Proxy = ProxyBuilder::new(&connection)
+            .destination(SERVICE_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .interface(SERVICE_NAME)
+            .unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .unwrap();
+        let mut beats = proxy.receive_signal("Beat").unwrap();
+        proxy.call_method("Pause", &()).unwrap();
+        proxy.call_method("SetSensitivity", &(150.0f64)).unwrap();
+        let error = proxy.call_method("SetPreset", &("chill")).unwrap_err();
+        assert!(
+            error.to_string().contains("No preset named chill"),
+            "{}",
+            error
+        );
+        assert!(proxy.call_method("SetSensitivity", &(-1.0f64)).is_err());
+        assert!(proxy.get_property::<bool>("Visible").unwrap());
+        assert_eq!(proxy.get_property::<String>("CurrentPreset").unwrap(), "");
+
+        ready_sender.send(()).unwrap();
+        let beat = beats.next().unwrap();
+        let bpm: f64 = beat.body().deserialize().unwrap();
+        assert_eq!(bpm, 120.0);
+        assert!(!proxy.get_property::<bool>("Visible").unwrap());
+    });
+
+    let mut commands = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut beat_sent = false;
+    while !client.is_finished() {
+        assert!(Instant::now() < deadline, "Timed out");
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut commands)
+            .unwrap();
+        if !beat_sent && ready_receiver.try_recv().is_ok() {
+            service.set_visible(false);
+            service.beat(Some(120.0));
+            beat_sent = true;
+        }
+    }
+    client.join().unwrap();
+    assert_eq!(
+        commands,
+        vec![
+            Command::Pause,
+            Command::Sensitivity(150.0),
+            Command::Preset("chill".into())
+        ]
+    );
+}
@@ -0,0 +1,124 @@
+use khronos_egl as egl;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use wallpaper_cava::app_config::Config;
+use wallpaper_cava::headless::{render_to_png, RenderOptions};
+use wallpaper_cava::software::Rasterizer;
+
+// llvmpipe versions may round differently, so channels may be a bit off, and a
+// few pixels along the bar edges may differ entirely.
+const CHANNEL_TOLERANCE: u8 = 3;
+const MAX_DIFFERENT_PIXELS: usize = 16;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+fn data(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name)
+}
+
+// Looks for a display the same way render_to_png does.
+fn egl_available() -> bool {
+    let display = unsafe {
+        egl::API
+            .get_platform_display(
+                PLATFORM_SURFACELESS_MESA,
+                egl::DEFAULT_DISPLAY,
+                &[egl::ATTRIB_NONE],
+            )
+            .ok()
+            .or_else(|| egl::API.get_display(egl::DEFAULT_DISPLAY))
+    };
+    let Some(display) = display else {
+        return false;
+    };
+    let available = egl::API.initialize(display).is_ok();
+    if available {
+        let _ = egl::API.terminate(display);
+    }
+    available
+}
+
+fn config() -> Config {
+    Config::parse(&fs::read_to_string(data("render.toml")).unwrap(), None).unwrap()
+}
+
+fn read_png(path: &PathBuf) -> (png::OutputInfo, Vec<u8>) {
+    let mut reader = png::Decoder::new(File::open(path).unwrap())
+        .read_info()
+        .unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    pixels.truncate(info.buffer_size());
+    (info, pixels)
+}
+
+fn assert_like_golden(pixels: &[u8]) {
+    let (golden_info, golden_pixels) = read_png(&data("render.png"));
+    assert_eq!(golden_info.color_type, png::ColorType::Rgba);
+    assert_eq!(pixels.len(), golden_pixels.len());
+    let different_pixels = pixels
+        .chunks_exact(4)
+        .zip(golden_pixels.chunks_exact(4))
+        .filter(|(pixel, golden)| {
+            pixel
+                .iter()
+                .zip(golden.iter())
+                .any(|(channel, golden)| channel.abs_diff(*golden) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    assert!(
+        different_pixels <= MAX_DIFFERENT_PIXELS,
+        "{} pixels differ from tests/data/render.png",
+        different_pixels
+    );
+}
+
+// Regenerate render.png with
+// `wallpaper-cava render --config tests/data/render.toml --frames tests/data/frames.raw --size 64x48 --out tests/data/render.png`
+// after changes that are meant to alter the output.
+#[test]
+#[ignore = "needs an EGL display, run with --ignored where there is one"]
+fn renders_like_the_golden_image() {
+    assert!(egl_available(), "No EGL display available");
+    let out =
+        std::env::temp_dir().join(format!("wallpaper-cava-render-{}.png", std::process::id()));
+    let options = RenderOptions {
+        config_filename: data("render.toml").to_str().unwrap().to_string(),
+        preset: None,
+        frames_filename: data("frames.raw").to_str().unwrap().to_string(),
+        width: 64,
+        height: 48,
+        out_filename: out.to_str().unwrap().to_string(),
+        frame_index: None,
+    };
+    render_to_png(&config(), &options).unwrap();
+
+    let (info, pixels) = read_png(&out);
+    fs::remove_file(&out).unwrap();
+    assert_eq!((info.width, info.height), (64, 48));
+    assert_like_golden(&pixels);
+}
+
+// The software renderer has to match the GL one, and runs without a GPU.
+#[test]
+fn rasterizes_like_the_golden_image() {
+    let config = config();
+    let bar_count = config.bars.amount as usize;
+    // The last of the 16-bit frames, like render_to_png picks.
+    let frames = fs::read(data("frames.raw")).unwrap();
+    let bar_values: Vec<f32> = frames[frames.len() - bar_count * 2..]
+        .chunks_exact(2)
+        .map(|value| u16::from_le_bytes([value[0], value[1]]) as f32 / 65535.0)
+        .collect();
+    let mut rasterizer = Rasterizer::new(&config, 64, 48);
+    let mut canvas = vec![0; 64 * 48 * 4];
+    rasterizer.rasterize(&mut canvas, &bar_values);
+    // B, G, R, A in memory.
+    let pixels: Vec<u8> = canvas
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+        .collect();
+    assert_like_golden(&pixels);
+}
@@ -0,0 +1,68 @@
+use calloop::EventLoop;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use wallpaper_cava::ipc::{send, Command, IpcServer, Reply};
+
+#[test]
+fn answers_commands_over_the_socket() {
+    let path = std::env::temp_dir().join(format!("wallpaper-cava-ipc-{}.sock", std::process::id()));
+    let mut event_loop: EventLoop<Vec<Command>> = EventLoop::try_new().unwrap();
+    let server = IpcServer::insert(
+        &event_loop.handle(),
+        &path,
+        |command, commands: &mut Vec<Command>| {
+            commands.push(command.clone());
+            match command {
+                Command::Preset(name) => Reply::error(format!("No preset named {}", name)),
+                _ => Reply::ok(),
+            }
+        },
+    )
+    .unwrap();
+    let error = IpcServer::insert(&event_loop.handle(), &path, |_, _: &mut Vec<Command>| {
+        Reply::ok()
+    })
+    .err()
+    .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+    let client_path = path.clone();
+    let client = thread::spawn(move || {
+        let send = |words: &[&str]| {
+            let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+            send(&client_path, &words).unwrap()
+        };
+        assert_eq!(send(&["pause"]), r#"{"ok":true}"#);
+        assert_eq!(send(&["sensitivity", "150"]), r#"{"ok":true}"#);
+        assert_eq!(
+            send(&["preset", "chill"]),
+            r#"{"ok":false,"error":"No preset named chill"}"#
+        );
+        assert_eq!(
+            send(&["dance"]),
+            r#"{"ok":false,"error":"Unknown command: dance"}"#
+        );
+    });
+
+    let mut commands = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !client.is_finished() {
+        assert!(Instant::now() < deadline, "Timed out");
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut commands)
+            .unwrap();
+    }
+    client.join().unwrap();
+    // Unparsable commands never reach the handler.
+    assert_eq!(
+        commands,
+        vec![
+            Command::Pause,
+            Command::Sensitivity(150.0),
+            Command::Preset("chill".into())
+        ]
+    );
+    drop(server);
+    assert!(!path.exists());
+}
@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command as Process, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use wallpaper_cava::app_config::Config;
+use wallpaper_cava::audio::{AudioSource, PulseSource};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Kills the private server and the players when the test ends, passing or not.
+struct Daemon {
+    server: Child,
+    players: Vec<Child>,
+    directory: PathBuf,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        for child in self.players.iter_mut().chain([&mut self.server]) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+impl Daemon {
+    // A pulseaudio of our own with the null sinks "first" and "second", no sound card needed.
+    fn start() -> Option<Self> {
+        let directory =
+            std::env::temp_dir().join(format!("wallpaper-cava-pulse-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).ok()?;
+        let socket = directory.join("native");
+        let script = directory.join("default.pa");
+        fs::write(
+            &script,
+            format!(
+                "load-module module-native-protocol-unix auth-anonymous=1 socket={}\n\
+                 load-module module-null-sink sink_name=first\n\
+                 load-module module-null-sink sink_name=second\n\
+                 set-default-sink first\n",
+                socket.display()
+            ),
+        )
+        .ok()?;
+        let server = Process::new("pulseaudio")
+            .args([
+                "-n",
+                "--daemonize=no",
+                "--exit-idle-time=-1",
+                "--use-pid-file=no",
+            ])
+            .arg("-F")
+            .arg(&script)
+            .env("HOME", &directory)
+            .env("XDG_RUNTIME_DIR", &directory)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let daemon = Daemon {
+            server,
+            players: Vec::new(),
+            directory,
+        };
+        // libpulse and pactl both pick the server up from here.
+        std::env::set_var("PULSE_SERVER", format!("unix:{}", socket.display()));
+        wait_for(|| (socket.exists() && pactl(&["info"]).is_some()).then_some(()))?;
+        Some(daemon)
+    }
+
+    // Plays noise into `sink` as an application called `name`.
+    fn play(&mut self, sink: &str, name: &str) {
+        let player = Process::new("pacat")
+            .args([
+                "--playback",
+                "--raw",
+                "--format=s16le",
+                "--rate=44100",
+                "--channels=2",
+            ])
+            .arg(format!("--device={}", sink))
+            .arg(format!("--client-name={}", name))
+            .arg(format!("--stream-name={}", name))
+            .stdin(fs::File::open("/dev/urandom").unwrap())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("pacat is missing");
+        self.players.push(player);
+    }
+}
+
+fn pactl(args: &[&str]) -> Option<String> {
+    let output = Process::new("pactl").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn wait_for<T>(mut check: impl FnMut() -> Option<T>) -> Option<T> {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        if let Some(value) = check() {
+            return Some(value);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+// The source every record stream of ours reads from, keyed by the stream's index.
+fn recording_sources() -> Vec<(String, String)> {
+    let sources = pactl(&["list", "short", "sources"]).unwrap_or_default();
+    let source_name = |index: &str| {
+        sources
+            .lines()
+            .map(|line| line.split('\t').collect::<Vec<_>>())
+            .find(|fields| fields[0] == index)
+            .map(|fields| fields[1].to_string())
+            .unwrap_or_default()
+    };
+    // index, owner module, client, source index, ...
+    pactl(&["list", "short", "source-outputs"])
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .filter(|fields| fields.len() > 3)
+        .map(|fields| (fields[0].to_string(), source_name(fields[3])))
+        .collect()
+}
+
+fn config(audio: &str) -> Config {
+    Config::parse(
+        &format!(
+            "[general]\nframerate = 60\nbackground_color = '#000000'\n[bars]\namount = 16\ngap = 0.1\n\
+             [colors]\ncolor_1 = '#ffffff'\n[smoothing]\n\
+             [audio]\nbackend = \"pulse\"\n{}\n",
+            audio
+        ),
+        None,
+    )
+    .unwrap()
+}
+
+// Counts frames and remembers whether any of them was loud.
+struct Frames {
+    count: Arc<AtomicUsize>,
+    loud: Arc<AtomicUsize>,
+}
+
+fn spawn(audio: &str) -> (PulseSource, Frames) {
+    let count = Arc::new(AtomicUsize::new(0));
+    let loud = Arc::new(AtomicUsize::new(0));
+    let frames = Frames {
+        count: count.clone(),
+        loud: loud.clone(),
+    };
+    let source = PulseSource::spawn(&config(audio), move |bar_values: &[f32]| {
+        count.fetch_add(1, Ordering::SeqCst);
+        if bar_values.iter().any(|value| *value > 0.0) {
+            loud.fetch_add(1, Ordering::SeqCst);
+        }
+    })
+    .expect("libpulse is missing");
+    (source, frames)
+}
+
+fn wait_for_source(name: &str) -> Option<String> {
+    wait_for(|| {
+        recording_sources()
+            .into_iter()
+            .find(|(_, source)| source == name)
+            .map(|(index, _)| index)
+    })
+}
+
+fn skip_without(binaries: &[&str]) -> bool {
+    let missing = binaries.iter().find(|binary| {
+        Process::new(binary)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_err()
+    });
+    if let Some(binary) = missing {
+        eprintln!("{} is not installed, skipping", binary);
+    }
+    missing.is_some() || !Path::new("/dev/urandom").exists()
+}
+
+// One test, the server address is process wide.
+#[test]
+fn captures_null_sinks() {
+    if skip_without(&["pulseaudio", "pactl", "pacat"]) {
+        return;
+    }
+    let Some(mut daemon) = Daemon::start() else {
+        eprintln!("pulseaudio failed to start, skipping");
+        return;
+    };
+    daemon.play("first", "first-player");
+    daemon.play("second", "second-player");
+
+    // The default sink's monitor, followed when the default sink changes.
+    let (source, frames) = spawn("");
+    let stream = wait_for_source("first.monitor").expect("never recorded first.monitor");
+    wait_for(|| (frames.loud.load(Ordering::SeqCst) > 10).then_some(()))
+        .expect("no loud frames arrived");
+    // Server changes other than the default sink keep the stream.
+    pactl(&["set-default-source", "second.monitor"]).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(wait_for_source("first.monitor"), Some(stream));
+    pactl(&["set-default-sink", "second"]).unwrap();
+    wait_for_source("second.monitor").expect("the stream didn't follow the default sink");
+    assert!(recording_sources()
+        .iter()
+        .all(|(_, source)| source != "first.monitor"));
+    let count = frames.count.load(Ordering::SeqCst);
+    wait_for(|| (frames.count.load(Ordering::SeqCst) > count + 10).then_some(()))
+        .expect("no frames after switching the default sink");
+    source.shutdown();
+    drop(source);
+    wait_for(|| recording_sources().is_empty().then_some(()))
+        .expect("the stream outlived shutdown");
+
+    // A named source stays put.
+    let (source, frames) = spawn("source = \"first.monitor\"");
+    wait_for_source("first.monitor").expect("never recorded the named source");
+    wait_for(|| (frames.loud.load(Ordering::SeqCst) > 10).then_some(()))
+        .expect("no loud frames from the named source");
+    drop(source);
+    wait_for(|| recording_sources().is_empty().then_some(())).expect("the stream outlived drop");
+
+    // A single application, on whatever sink it plays.
+    let (source, frames) = spawn("stream = \"first-player\"");
+    wait_for_source("first.monitor").expect("never recorded the stream's sink");
+    wait_for(|| (frames.loud.load(Ordering::SeqCst) > 10).then_some(()))
+        .expect("no loud frames from the stream");
+    drop(source);
+}